@@ -0,0 +1,173 @@
+use actix_web::{error::PayloadError, http::StatusCode, web, HttpResponse, ResponseError};
+use serde::Serialize;
+use std::fmt;
+
+/// Errors of the HTTP handlers, they're answered with their status code and a
+/// JSON body such as `{"error": "project_not_found", "message": "..."}`.
+#[derive(Debug)]
+pub enum ApiError {
+    /// No project with the name is configured.
+    ProjectNotFound(String),
+    JobNotFound(i64),
+    /// The token or signature of the request doesn't match.
+    Unauthorized(&'static str),
+    Forbidden(&'static str),
+    /// The request couldn't be parsed.
+    BadRequest(String),
+    /// The payload is larger than the limit in bytes.
+    PayloadTooLarge(usize),
+    /// The job queue of the project is full.
+    Unavailable(String),
+    Internal(String),
+}
+
+#[derive(Serialize)]
+struct ErrorBody {
+    error: &'static str,
+    message: String,
+}
+
+/// Response for a webhook that was accepted without queueing a deployment.
+#[derive(Serialize)]
+struct Ignored {
+    status: &'static str,
+    reason: String,
+}
+
+impl ApiError {
+    /// Identifier of the error for clients.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::ProjectNotFound(_) => "project_not_found",
+            ApiError::JobNotFound(_) => "job_not_found",
+            ApiError::Unauthorized(_) => "unauthorized",
+            ApiError::Forbidden(_) => "forbidden",
+            ApiError::BadRequest(_) => "bad_request",
+            ApiError::PayloadTooLarge(_) => "payload_too_large",
+            ApiError::Unavailable(_) => "unavailable",
+            ApiError::Internal(_) => "internal",
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ApiError::ProjectNotFound(name) => write!(f, "project {} is not configured", name),
+            ApiError::JobNotFound(id) => write!(f, "job {} not found", id),
+            ApiError::Unauthorized(reason) => write!(f, "{}", reason),
+            ApiError::Forbidden(reason) => write!(f, "{}", reason),
+            ApiError::BadRequest(reason) => write!(f, "{}", reason),
+            ApiError::PayloadTooLarge(limit) => {
+                write!(f, "payload is larger than {} bytes", limit)
+            }
+            ApiError::Unavailable(reason) => write!(f, "{}", reason),
+            ApiError::Internal(reason) => write!(f, "internal error: {}", reason),
+        }
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::ProjectNotFound(_) | ApiError::JobNotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            ApiError::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let message = match self {
+            ApiError::Internal(reason) => {
+                let log = slog_scope::logger();
+                error!(log, "request failed"; "error" => reason);
+                // the details are only logged
+                "internal server error".to_string()
+            }
+            _ => self.to_string(),
+        };
+        HttpResponse::build(self.status_code()).json(ErrorBody {
+            error: self.code(),
+            message,
+        })
+    }
+}
+
+impl From<serde_json::Error> for ApiError {
+    fn from(e: serde_json::Error) -> Self {
+        ApiError::BadRequest(format!("invalid payload: {}", e))
+    }
+}
+
+impl From<PayloadError> for ApiError {
+    fn from(e: PayloadError) -> Self {
+        ApiError::BadRequest(e.to_string())
+    }
+}
+
+impl From<rusqlite::Error> for ApiError {
+    fn from(e: rusqlite::Error) -> Self {
+        ApiError::Internal(e.to_string())
+    }
+}
+
+/// Answer a webhook that doesn't start a deployment with the reason.
+pub fn ignored(reason: String) -> HttpResponse {
+    HttpResponse::Accepted().json(Ignored {
+        status: "ignored",
+        reason,
+    })
+}
+
+/// JSON bodies that can't be parsed are answered with a `bad_request` error.
+pub fn json_config() -> web::JsonConfig {
+    web::JsonConfig::default()
+        .error_handler(|e, _| ApiError::BadRequest(format!("invalid body: {}", e)).into())
+}
+
+/// Query strings that can't be parsed are answered with a `bad_request` error.
+pub fn query_config() -> web::QueryConfig {
+    web::QueryConfig::default()
+        .error_handler(|e, _| ApiError::BadRequest(format!("invalid query: {}", e)).into())
+}
+
+/// Paths that can't be parsed, such as a job id that isn't a number, are
+/// answered with a `bad_request` error.
+pub fn path_config() -> web::PathConfig {
+    web::PathConfig::default()
+        .error_handler(|e, _| ApiError::BadRequest(format!("invalid path: {}", e)).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_maps_errors_to_status_codes() {
+        let errors = vec![
+            (ApiError::ProjectNotFound("sample".to_string()), 404),
+            (ApiError::JobNotFound(1), 404),
+            (ApiError::Unauthorized("invalid token"), 401),
+            (ApiError::Forbidden("job management is disabled"), 403),
+            (ApiError::BadRequest("invalid payload".to_string()), 400),
+            (ApiError::PayloadTooLarge(1024), 413),
+            (ApiError::Unavailable("queue is full".to_string()), 503),
+            (ApiError::Internal("database is locked".to_string()), 500),
+        ];
+        for (error, status) in errors {
+            assert_eq!(error.status_code().as_u16(), status, "{}", error.code());
+        }
+    }
+
+    #[test]
+    fn it_maps_parse_failures_to_bad_requests() {
+        let e = serde_json::from_str::<serde_json::Value>("{").unwrap_err();
+        let error = ApiError::from(e);
+        assert_eq!(error.code(), "bad_request");
+        assert!(error.to_string().starts_with("invalid payload: "));
+    }
+}
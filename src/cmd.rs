@@ -1,14 +1,26 @@
 extern crate clap;
 
-use clap::{App, Arg};
+use clap::{App, Arg, SubCommand};
 use std::ffi::OsString;
 
+use crate::config::ServerSettings;
+
+const DEFAULT_DATABASE: &str = "/var/lib/shook/shook.db"; // job history database
+
+/// Command line arguments, the server settings are only set when they're
+/// given so they can fall back to the environment and the configuration.
 #[derive(Debug, PartialEq)]
 pub struct ShookArgs {
-    pub port: String,
-    pub host: String,
+    pub port: Option<u16>,
+    pub host: Option<String>,
     pub config: String,
-    pub level: slog::Level,
+    pub database: String,
+    pub watch: bool,
+    /// Only validate the configuration and exit.
+    pub check_config: bool,
+    /// Read the secrets while checking instead of only checking references.
+    pub resolve_secrets: bool,
+    pub level: Option<slog::Level>,
 }
 
 impl ShookArgs {
@@ -30,6 +42,7 @@ impl ShookArgs {
                     .long("port")
                     .short("p")
                     .takes_value(true)
+                    .validator(|port| port.parse::<u16>().map(|_| ()).map_err(|e| e.to_string()))
                     .help("port to listen on"),
             )
             .arg(
@@ -44,7 +57,20 @@ impl ShookArgs {
                     .long("config")
                     .short("c")
                     .takes_value(true)
-                    .help("configuration file to load"),
+                    .help("configuration file or directory to load"),
+            )
+            .arg(
+                Arg::with_name("database")
+                    .long("database")
+                    .short("d")
+                    .takes_value(true)
+                    .help("job history database file, defaults to /var/lib/shook/shook.db"),
+            )
+            .arg(
+                Arg::with_name("watch")
+                    .long("watch")
+                    .short("w")
+                    .help("reload the configuration when the file changes"),
             )
             .arg(
                 Arg::with_name("verbose")
@@ -53,22 +79,53 @@ impl ShookArgs {
                     .multiple(true)
                     .help("verbose output"),
             )
+            .subcommand(
+                SubCommand::with_name("check-config")
+                    .about("validate the configuration file and exit")
+                    .arg(
+                        Arg::with_name("file")
+                            .index(1)
+                            .help("configuration file to check, defaults to --config"),
+                    )
+                    .arg(
+                        Arg::with_name("resolve-secrets")
+                            .long("resolve-secrets")
+                            .help("read the referenced secrets instead of only checking them"),
+                    ),
+            )
             .get_matches_from_safe(args)?;
 
-        let port = matches.value_of("port").unwrap_or("5000");
-        let host = matches.value_of("host").unwrap_or("0.0.0.0");
-        let config = matches.value_of("config").unwrap_or("config.yml");
+        let check = matches.subcommand_matches("check-config");
+        let config = check
+            .and_then(|check| check.value_of("file"))
+            .or_else(|| matches.value_of("config"))
+            .unwrap_or("config.yml");
+        let database = matches.value_of("database").unwrap_or(DEFAULT_DATABASE);
         let level = match matches.occurrences_of("verbose") {
-            0 => slog::Level::Info,
-            1 => slog::Level::Debug,
-            _ => slog::Level::Trace,
+            0 => None,
+            1 => Some(slog::Level::Debug),
+            _ => Some(slog::Level::Trace),
         };
 
         Ok(ShookArgs {
-            port: port.to_string(),
-            host: host.to_string(),
+            port: matches.value_of("port").and_then(|port| port.parse().ok()),
+            host: matches.value_of("host").map(str::to_string),
             config: config.to_string(),
+            database: database.to_string(),
+            watch: matches.is_present("watch"),
+            check_config: check.is_some(),
+            resolve_secrets: check.is_some_and(|check| check.is_present("resolve-secrets")),
             level,
         })
     }
+
+    /// Server settings given on the command line.
+    pub fn server(&self) -> ServerSettings {
+        ServerSettings {
+            host: self.host.clone(),
+            port: self.port,
+            log_level: self.level,
+            ..Default::default()
+        }
+    }
 }
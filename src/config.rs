@@ -1,50 +1,1067 @@
-use serde::Deserialize;
+use serde::{de, Deserialize, Deserializer};
 use std::collections::HashMap;
-use std::process::Command;
-use std::str;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
 
-#[derive(Clone, Debug, Deserialize, PartialEq)]
+use crate::git::{self, Credentials, Protocol};
+use crate::job::JobHandle;
+use crate::locate;
+use crate::rules::{self, Event, Rule};
+use crate::runner::{self, Cancel, Outcome, Stream};
+use crate::secret::{self, Secret};
+
+/// How a secret is resolved while loading, either read or only checked.
+type Resolve = fn(&mut Secret) -> Result<(), String>;
+
+/// Timeout of cleanup commands when neither they nor the project have one.
+const CLEANUP_TIMEOUT: Duration = Duration::from_secs(300);
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
 #[serde(rename_all = "lowercase")]
 pub enum Provider {
+    #[default]
     GitLab,
     GitHub,
 }
 
-impl Default for Provider {
-    fn default() -> Self {
-        Provider::GitLab
+impl Provider {
+    /// Name of the provider as it's written in the configuration.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Provider::GitLab => "gitlab",
+            Provider::GitHub => "github",
+        }
+    }
+
+    /// Username of HTTPS access tokens, GitLab deploy tokens have their own.
+    pub fn token_username(&self) -> &'static str {
+        match self {
+            Provider::GitLab => "oauth2",
+            Provider::GitHub => "x-access-token",
+        }
+    }
+
+    /// Rules used when a project doesn't configure any, a merged request into
+    /// `main` is deployed.
+    pub fn default_rules(&self) -> Vec<Rule> {
+        match self {
+            Provider::GitLab => vec![Rule::new(
+                &["merge_request"],
+                &["main"],
+                &["merge"],
+                &["merged"],
+            )],
+            // GitHub sends "closed" action when PR is merged
+            Provider::GitHub => vec![Rule::new(
+                &["pull_request"],
+                &["main"],
+                &["closed"],
+                &["merged"],
+            )],
+        }
+    }
+}
+
+/// How the repository of a project is cloned and fetched, at most one kind of
+/// credentials can be set.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct GitSettings {
+    /// Clone URL of the repository, events of other repositories are refused.
+    /// It's required with credentials so they're only sent to it.
+    pub repository: Option<String>,
+    /// Clone from the HTTPS or the SSH URL of the repository, SSH when a key
+    /// or the agent is used unless it's set.
+    protocol: Option<Protocol>,
+    /// Private SSH key file.
+    ssh_key: Option<PathBuf>,
+    /// Passphrase the SSH key is encrypted with.
+    passphrase: Option<Secret>,
+    /// Authenticate with the keys of the running ssh-agent.
+    #[serde(default)]
+    ssh_agent: bool,
+    /// Known hosts file the SSH host key of the server has to be in, defaults
+    /// to the one of the user.
+    known_hosts: Option<PathBuf>,
+    /// HTTPS username, the default depends on the provider.
+    username: Option<String>,
+    /// HTTPS access token or GitLab deploy token.
+    token: Option<Secret>,
+}
+
+impl GitSettings {
+    pub fn protocol(&self) -> Protocol {
+        match (self.protocol, &self.repository) {
+            (Some(protocol), _) => protocol,
+            (None, Some(repository)) => Protocol::of(repository),
+            (None, None) if self.ssh_key.is_some() || self.ssh_agent => Protocol::Ssh,
+            (None, None) => Protocol::Https,
+        }
+    }
+
+    /// Known hosts file, `~/.ssh/known_hosts` unless it's set.
+    fn known_hosts(&self) -> PathBuf {
+        match &self.known_hosts {
+            Some(path) => path.clone(),
+            None => std::env::var_os("HOME")
+                .map(PathBuf::from)
+                .unwrap_or_default()
+                .join(".ssh/known_hosts"),
+        }
+    }
+
+    fn validate(&self, path: &str) -> Vec<Problem> {
+        let mut problems = Vec::new();
+        let credentials = [self.ssh_key.is_some(), self.ssh_agent, self.token.is_some()];
+        if credentials.iter().filter(|set| **set).count() > 1 {
+            problems.push(Problem::new(
+                path.to_string(),
+                "only one of ssh_key, ssh_agent and token can be set".to_string(),
+            ));
+        } else if self.token.is_some() && self.protocol() == Protocol::Ssh {
+            problems.push(Problem::new(
+                format!("{}.protocol", path),
+                "token requires the https protocol".to_string(),
+            ));
+        } else if (self.ssh_key.is_some() || self.ssh_agent) && self.protocol() == Protocol::Https {
+            problems.push(Problem::new(
+                format!("{}.protocol", path),
+                "ssh_key and ssh_agent require the ssh protocol".to_string(),
+            ));
+        }
+        match &self.repository {
+            Some(repository) if git::repository_path(repository).is_none() => {
+                problems.push(Problem::new(
+                    format!("{}.repository", path),
+                    format!("`{}` isn't a clone URL with a repository path", repository),
+                ));
+            }
+            Some(repository) if Protocol::of(repository) != self.protocol() => {
+                problems.push(Problem::new(
+                    format!("{}.protocol", path),
+                    "protocol doesn't match the repository URL".to_string(),
+                ));
+            }
+            Some(_) => (),
+            None if credentials.contains(&true) => {
+                problems.push(Problem::new(
+                    path.to_string(),
+                    "repository is required with credentials, they're only sent to it".to_string(),
+                ));
+            }
+            None => (),
+        }
+        if self.known_hosts.is_some() && self.ssh_key.is_none() && !self.ssh_agent {
+            problems.push(Problem::new(
+                format!("{}.known_hosts", path),
+                "known_hosts requires ssh_key or ssh_agent".to_string(),
+            ));
+        }
+        if self.passphrase.is_some() && self.ssh_key.is_none() {
+            problems.push(Problem::new(
+                format!("{}.passphrase", path),
+                "passphrase requires ssh_key".to_string(),
+            ));
+        }
+        if self.token.as_ref().is_some_and(Secret::is_empty) {
+            problems.push(Problem::new(
+                format!("{}.token", path),
+                "token is empty".to_string(),
+            ));
+        }
+        problems
     }
 }
 
 #[derive(Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Project {
     pub name: String,
-    pub token: String,
+    pub token: Secret,
     #[serde(default)]
     pub provider: Provider,
-    env: Option<HashMap<String, String>>,
-    commands: Vec<String>,
+    env: Option<HashMap<String, Secret>>,
+    rules: Option<Vec<Rule>>,
+    /// Only run the newest of the jobs that are waiting for this project.
+    #[serde(default)]
+    pub coalesce: bool,
+    /// Time in seconds the whole command list is allowed to run.
+    timeout: Option<u64>,
+    /// Directory commands run in, relative paths are resolved against the
+    /// repository checkout.
+    workdir: Option<String>,
+    /// Run all commands in a single shell so `cd` and exported variables carry
+    /// over between them.
+    #[serde(default)]
+    pub script: bool,
+    commands: Vec<Command>,
+    /// Commands run after a job of the project is cancelled.
+    #[serde(default)]
+    cleanup: Vec<Command>,
+    /// Protocol and credentials of the repository.
+    #[serde(default)]
+    pub git: GitSettings,
+    /// File the project was read from when the configuration is a directory.
+    #[serde(skip)]
+    source: Option<PathBuf>,
 }
 
-#[derive(Deserialize)]
-pub struct Config {
-    projects: Vec<Project>,
+/// A project command, either a plain shell command or a mapping with options.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    Shell(String),
+    Options(CommandOptions),
+}
+
+/// Mapping form of a command.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct CommandOptions {
+    run: String,
+    /// Keep running the remaining commands when this one fails.
+    #[serde(default)]
+    continue_on_error: bool,
+    /// Time in seconds the command is allowed to run.
+    timeout: Option<u64>,
+}
+
+/// The form is picked by the node type, so problems of a mapping are reported
+/// with the field they're about.
+impl<'de> Deserialize<'de> for Command {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct CommandVisitor;
+
+        impl<'de> de::Visitor<'de> for CommandVisitor {
+            type Value = Command;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a shell command or a mapping with `run`")
+            }
+
+            fn visit_str<E: de::Error>(self, value: &str) -> Result<Command, E> {
+                Ok(Command::Shell(value.to_string()))
+            }
+
+            fn visit_map<A: de::MapAccess<'de>>(self, map: A) -> Result<Command, A::Error> {
+                CommandOptions::deserialize(de::value::MapAccessDeserializer::new(map))
+                    .map(Command::Options)
+            }
+        }
+
+        deserializer.deserialize_any(CommandVisitor)
+    }
+}
+
+impl Command {
+    pub fn run(&self) -> &str {
+        match self {
+            Command::Shell(run) => run,
+            Command::Options(options) => &options.run,
+        }
+    }
+
+    pub fn continue_on_error(&self) -> bool {
+        match self {
+            Command::Shell(_) => false,
+            Command::Options(options) => options.continue_on_error,
+        }
+    }
+
+    pub fn timeout(&self) -> Option<Duration> {
+        match self {
+            Command::Shell(_) => None,
+            Command::Options(options) => options.timeout.map(Duration::from_secs),
+        }
+    }
 }
 
-pub fn should_deploy(branch: String, action: String, status: String) -> bool {
-    branch == "main" && action == "merge" && status == "merged"
+/// Limits of the job queue that runs deployments.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct QueueSettings {
+    /// Maximum number of jobs running at once across all projects.
+    #[serde(default = "QueueSettings::default_concurrency")]
+    pub concurrency: usize,
+    /// Maximum number of jobs waiting per project.
+    #[serde(default = "QueueSettings::default_capacity")]
+    pub capacity: usize,
+}
+
+impl QueueSettings {
+    fn validate(&self) -> Vec<Problem> {
+        let mut problems = Vec::new();
+        if self.concurrency == 0 {
+            problems.push(Problem::new(
+                "queue.concurrency".to_string(),
+                "concurrency has to be greater than 0".to_string(),
+            ));
+        }
+        if self.capacity == 0 {
+            problems.push(Problem::new(
+                "queue.capacity".to_string(),
+                "capacity has to be greater than 0".to_string(),
+            ));
+        }
+        problems
+    }
+
+    fn default_concurrency() -> usize {
+        4
+    }
+
+    fn default_capacity() -> usize {
+        16
+    }
+}
+
+impl Default for QueueSettings {
+    fn default() -> Self {
+        QueueSettings {
+            concurrency: QueueSettings::default_concurrency(),
+            capacity: QueueSettings::default_capacity(),
+        }
+    }
+}
+
+/// Certificate chain and private key of the server, both PEM files.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct TlsSettings {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+/// Settings of the HTTP server, unset values use the defaults. Command line
+/// flags take precedence over environment variables, which take precedence
+/// over the configuration file.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ServerSettings {
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    #[serde(default, deserialize_with = "deserialize_level")]
+    pub log_level: Option<slog::Level>,
+    /// Directory repositories are cloned into.
+    pub cache_dir: Option<PathBuf>,
+    /// Maximum size of a webhook payload in bytes.
+    pub payload_limit: Option<usize>,
+    /// Number of worker threads, defaults to the number of CPUs.
+    pub workers: Option<usize>,
+    pub tls: Option<TlsSettings>,
+}
+
+/// Log level by its name, such as `debug` or `warning`.
+pub fn parse_level(name: &str) -> Result<slog::Level, String> {
+    match name.to_lowercase().as_str() {
+        "critical" => Ok(slog::Level::Critical),
+        "error" => Ok(slog::Level::Error),
+        "warning" | "warn" => Ok(slog::Level::Warning),
+        "info" => Ok(slog::Level::Info),
+        "debug" => Ok(slog::Level::Debug),
+        "trace" => Ok(slog::Level::Trace),
+        _ => Err(format!(
+            "unknown log level `{}`, expected one of critical, error, warning, info, debug, trace",
+            name
+        )),
+    }
+}
+
+fn deserialize_level<'de, D>(deserializer: D) -> Result<Option<slog::Level>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let name = String::deserialize(deserializer)?;
+    parse_level(&name).map(Some).map_err(de::Error::custom)
+}
+
+/// Parse the environment variable `name`, `None` when it isn't set.
+fn parse_var<T, F>(var: &F, name: &str, problems: &mut Vec<Problem>) -> Option<T>
+where
+    T: std::str::FromStr,
+    T::Err: fmt::Display,
+    F: Fn(&str) -> Option<String>,
+{
+    let value = var(name)?;
+    match value.parse() {
+        Ok(value) => Some(value),
+        Err(e) => {
+            problems.push(Problem::new(name.to_string(), e.to_string()));
+            None
+        }
+    }
+}
+
+impl ServerSettings {
+    pub const DEFAULT_HOST: &'static str = "0.0.0.0";
+    pub const DEFAULT_PORT: u16 = 5000;
+    pub const DEFAULT_CACHE_DIR: &'static str = "/var/cache/shook";
+    pub const DEFAULT_PAYLOAD_LIMIT: usize = 262_144; // 256k
+
+    /// Settings of the `SHOOK_*` environment variables.
+    pub fn from_env() -> Result<Self, Vec<Problem>> {
+        Self::from_vars(|name| std::env::var(name).ok())
+    }
+
+    /// Settings of the `SHOOK_*` variables that `var` looks up.
+    pub fn from_vars<F>(var: F) -> Result<Self, Vec<Problem>>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let mut problems = Vec::new();
+        let log_level = var("SHOOK_LOG_LEVEL").and_then(|name| match parse_level(&name) {
+            Ok(level) => Some(level),
+            Err(message) => {
+                problems.push(Problem::new("SHOOK_LOG_LEVEL".to_string(), message));
+                None
+            }
+        });
+        let tls = match (var("SHOOK_TLS_CERT"), var("SHOOK_TLS_KEY")) {
+            (Some(cert), Some(key)) => Some(TlsSettings {
+                cert: PathBuf::from(cert),
+                key: PathBuf::from(key),
+            }),
+            (None, None) => None,
+            _ => {
+                problems.push(Problem::new(
+                    "SHOOK_TLS_CERT".to_string(),
+                    "SHOOK_TLS_CERT and SHOOK_TLS_KEY have to be set together".to_string(),
+                ));
+                None
+            }
+        };
+        let settings = ServerSettings {
+            host: var("SHOOK_HOST"),
+            port: parse_var(&var, "SHOOK_PORT", &mut problems),
+            log_level,
+            cache_dir: var("SHOOK_CACHE_DIR").map(PathBuf::from),
+            payload_limit: parse_var(&var, "SHOOK_PAYLOAD_LIMIT", &mut problems),
+            workers: parse_var(&var, "SHOOK_WORKERS", &mut problems),
+            tls,
+        };
+        problems.extend(settings.validate(""));
+
+        match problems.is_empty() {
+            true => Ok(settings),
+            false => Err(problems),
+        }
+    }
+
+    /// Settings of `self`, with the unset ones taken from `fallback`.
+    pub fn or(self, fallback: ServerSettings) -> ServerSettings {
+        ServerSettings {
+            host: self.host.or(fallback.host),
+            port: self.port.or(fallback.port),
+            log_level: self.log_level.or(fallback.log_level),
+            cache_dir: self.cache_dir.or(fallback.cache_dir),
+            payload_limit: self.payload_limit.or(fallback.payload_limit),
+            workers: self.workers.or(fallback.workers),
+            tls: self.tls.or(fallback.tls),
+        }
+    }
+
+    /// Problems of the settings, `prefix` is the path of the settings.
+    fn validate(&self, prefix: &str) -> Vec<Problem> {
+        let mut problems = Vec::new();
+        let path = |name: &str| match prefix.is_empty() {
+            true => format!("SHOOK_{}", name.to_uppercase()),
+            false => format!("{}.{}", prefix, name),
+        };
+        if self.payload_limit == Some(0) {
+            problems.push(Problem::new(
+                path("payload_limit"),
+                "payload limit has to be greater than 0".to_string(),
+            ));
+        }
+        if self.workers == Some(0) {
+            problems.push(Problem::new(
+                path("workers"),
+                "number of workers has to be greater than 0".to_string(),
+            ));
+        }
+        problems
+    }
+
+    pub fn host(&self) -> &str {
+        self.host.as_deref().unwrap_or(Self::DEFAULT_HOST)
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port.unwrap_or(Self::DEFAULT_PORT)
+    }
+
+    pub fn log_level(&self) -> slog::Level {
+        self.log_level.unwrap_or(slog::Level::Info)
+    }
+
+    pub fn cache_dir(&self) -> PathBuf {
+        match &self.cache_dir {
+            Some(cache_dir) => cache_dir.clone(),
+            None => PathBuf::from(Self::DEFAULT_CACHE_DIR),
+        }
+    }
+
+    pub fn payload_limit(&self) -> usize {
+        self.payload_limit.unwrap_or(Self::DEFAULT_PAYLOAD_LIMIT)
+    }
+}
+
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Token required to manage jobs, job management is disabled without it.
+    pub admin_token: Option<Secret>,
+    /// Settings of the server, they're applied after a restart.
+    #[serde(default)]
+    pub server: ServerSettings,
+    #[serde(default)]
+    pub queue: QueueSettings,
+    #[serde(default)]
+    projects: Vec<Project>,
 }
 
 impl Project {
     pub fn env(&self) -> HashMap<String, String> {
         match &self.env {
             None => HashMap::new(),
+            Some(value) => value
+                .iter()
+                .map(|(name, value)| (name.clone(), value.expose().to_string()))
+                .collect(),
+        }
+    }
+
+    /// Values of the token, the `env` variables and the git credentials,
+    /// they're redacted from the command output whether they're written in
+    /// the configuration or referenced.
+    pub fn secrets(&self) -> Vec<String> {
+        let env = self.env.iter().flat_map(|env| env.values());
+        let git = self.git.passphrase.iter().chain(self.git.token.iter());
+        std::iter::once(&self.token)
+            .chain(env)
+            .chain(git)
+            .map(|secret| secret.expose().to_string())
+            .collect()
+    }
+
+    /// Resolve the secrets of the project with `resolve`, `path` is its path
+    /// in the configuration.
+    fn resolve(&mut self, path: &str, resolve: Resolve) -> Vec<Problem> {
+        let mut problems = Vec::new();
+        if let Err(message) = resolve(&mut self.token) {
+            problems.push(Problem::new(format!("{}.token", path), message));
+        }
+        for (name, value) in self.env.iter_mut().flat_map(|env| env.iter_mut()) {
+            if let Err(message) = resolve(value) {
+                problems.push(Problem::new(format!("{}.env.{}", path, name), message));
+            }
+        }
+        let git = vec![
+            ("passphrase", self.git.passphrase.as_mut()),
+            ("token", self.git.token.as_mut()),
+        ];
+        for (name, value) in git {
+            if let Some(Err(message)) = value.map(resolve) {
+                problems.push(Problem::new(format!("{}.git.{}", path, name), message));
+            }
+        }
+        problems
+    }
+
+    /// Credentials the repository is cloned and fetched with, tokens without a
+    /// username use the one the provider expects for access tokens.
+    pub fn credentials(&self) -> Credentials {
+        let git = &self.git;
+        if let Some(path) = &git.ssh_key {
+            Credentials::SshKey {
+                path: path.clone(),
+                passphrase: git.passphrase.as_ref().map(|p| p.expose().to_string()),
+                known_hosts: git.known_hosts(),
+            }
+        } else if git.ssh_agent {
+            Credentials::SshAgent {
+                known_hosts: git.known_hosts(),
+            }
+        } else if let Some(token) = &git.token {
+            Credentials::Token {
+                username: match &git.username {
+                    Some(username) => username.clone(),
+                    None => self.provider.token_username().to_string(),
+                },
+                token: token.expose().to_string(),
+            }
+        } else {
+            Credentials::None
+        }
+    }
+
+    pub fn rules(&self) -> Vec<Rule> {
+        match &self.rules {
+            None => self.provider.default_rules(),
             Some(value) => value.clone(),
         }
     }
+
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout.map(Duration::from_secs)
+    }
+
+    /// Directory to run the commands in for a repository checkout, `None` keeps
+    /// the directory of the service.
+    pub fn workdir(&self, checkout: Option<&Path>) -> Option<PathBuf> {
+        match (&self.workdir, checkout) {
+            (Some(workdir), Some(checkout)) => Some(checkout.join(workdir)),
+            (Some(workdir), None) => Some(PathBuf::from(workdir)),
+            (None, checkout) => checkout.map(Path::to_path_buf),
+        }
+    }
+
+    /// Commands to run, in script mode they're combined into one bash script
+    /// that exits at the first failing command that doesn't continue on error.
+    pub fn commands(&self) -> Vec<Command> {
+        if !self.script {
+            return self.commands.clone();
+        }
+
+        let mut script = vec!["set -e".to_string()];
+        for command in self.commands.iter() {
+            match command.continue_on_error() {
+                true => script.push(format!("{{\n{}\n}} || true", command.run())),
+                false => script.push(command.run().to_string()),
+            }
+        }
+        vec![Command::Shell(script.join("\n"))]
+    }
+
+    pub fn should_deploy(&self, event: &Event) -> bool {
+        rules::should_deploy(&self.rules(), event)
+    }
+}
+
+/// Problem found in a configuration, `path` is the offending value such as
+/// `projects[1].token`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Problem {
+    /// File of the problem when the configuration is a directory.
+    pub file: Option<PathBuf>,
+    pub path: String,
+    pub message: String,
+    /// Line and column of the value in the file, when it's known.
+    pub position: Option<(usize, usize)>,
+}
+
+impl Problem {
+    fn new(path: String, message: String) -> Self {
+        Problem {
+            file: None,
+            path,
+            message,
+            position: None,
+        }
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{}:", file.display())?;
+            if self.position.is_none() {
+                write!(f, " ")?;
+            }
+        }
+        if let Some((line, column)) = self.position {
+            write!(f, "{}:{}: ", line, column)?;
+        }
+        if !self.path.is_empty() {
+            write!(f, "{}: ", self.path)?;
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    Parse(serde_yaml::Error),
+    /// The configuration parsed but isn't usable.
+    Invalid(Vec<Problem>),
+}
+
+impl ConfigError {
+    /// Problems to report for the error, parse errors are located at the line
+    /// and column they were found at.
+    pub fn problems(&self) -> Vec<Problem> {
+        match self {
+            ConfigError::Io(e) => vec![Problem::new(String::new(), e.to_string())],
+            ConfigError::Parse(e) => {
+                let mut message = e.to_string();
+                let position = e.location().map(|location| {
+                    // the location is already part of the problem
+                    if let Some(index) = message.rfind(" at line ") {
+                        message.truncate(index);
+                    }
+                    (location.line(), location.column())
+                });
+                vec![Problem {
+                    file: None,
+                    path: String::new(),
+                    message,
+                    position,
+                }]
+            }
+            ConfigError::Invalid(problems) => problems.clone(),
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io(e) => write!(f, "failed to read configuration: {}", e),
+            ConfigError::Parse(e) => write!(f, "failed to parse configuration: {}", e),
+            ConfigError::Invalid(problems) => {
+                let problems = problems
+                    .iter()
+                    .map(Problem::to_string)
+                    .collect::<Vec<String>>();
+                write!(f, "invalid configuration: {}", problems.join(", "))
+            }
+        }
+    }
+}
+
+/// Configuration that can be replaced while the server runs, handlers take a
+/// snapshot of it so jobs keep the configuration they were started with.
+pub struct SharedConfig {
+    current: RwLock<Arc<Config>>,
+}
+
+impl SharedConfig {
+    pub fn new(config: Config) -> Self {
+        SharedConfig {
+            current: RwLock::new(Arc::new(config)),
+        }
+    }
+
+    pub fn get(&self) -> Arc<Config> {
+        self.current.read().unwrap().clone()
+    }
+
+    pub fn replace(&self, config: Config) {
+        *self.current.write().unwrap() = Arc::new(config);
+    }
+}
+
+/// Name of the global settings file of a configuration directory.
+pub const GLOBAL_FILE: &str = "shook.yml";
+
+/// Project files of a configuration directory, every YAML file besides the
+/// global settings file in the order of their names. Hidden files are skipped.
+pub fn project_files(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let name = match path.file_name().and_then(|name| name.to_str()) {
+            Some(name) => name,
+            None => continue,
+        };
+        let yaml = path
+            .extension()
+            .is_some_and(|extension| extension == "yml" || extension == "yaml");
+        if yaml && path.is_file() && !name.starts_with('.') && name != GLOBAL_FILE {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Problems of a file that failed to load, they're reported in that file.
+fn file_problems(file: &Path, error: ConfigError) -> Vec<Problem> {
+    let mut problems = error.problems();
+    for problem in problems.iter_mut() {
+        problem.file = Some(file.to_path_buf());
+    }
+    problems
 }
 
 impl Config {
+    /// Read and validate the configuration, `path` is either a file or a
+    /// directory of project files.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, ConfigError> {
+        Self::load_with(path.as_ref(), Secret::resolve)
+    }
+
+    /// Read and validate the configuration like `load`, but only check the
+    /// syntax of the secret references so it can be checked where the secrets
+    /// aren't available.
+    pub fn load_unresolved<P: AsRef<Path>>(path: P) -> Result<Self, ConfigError> {
+        Self::load_with(path.as_ref(), Secret::check)
+    }
+
+    fn load_with(path: &Path, resolve: Resolve) -> Result<Self, ConfigError> {
+        if path.is_dir() {
+            return Self::load_dir(path, resolve);
+        }
+        let input = fs::read_to_string(path).map_err(ConfigError::Io)?;
+        Self::parse_with(&input, resolve)
+    }
+
+    /// Read a configuration directory, the optional `shook.yml` holds the
+    /// global settings and every other YAML file holds one project. Problems
+    /// are reported in the file they're found in.
+    fn load_dir(dir: &Path, resolve: Resolve) -> Result<Self, ConfigError> {
+        let global = dir.join(GLOBAL_FILE);
+        let mut inputs = HashMap::new();
+        let mut problems = Vec::new();
+
+        let mut config = match fs::read_to_string(&global) {
+            Ok(input) => {
+                let config = serde_yaml::from_str::<Config>(&input);
+                inputs.insert(global.clone(), input);
+                match config {
+                    Ok(config) => config,
+                    Err(e) => {
+                        problems.extend(file_problems(&global, ConfigError::Parse(e)));
+                        Config::default()
+                    }
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Config::default(),
+            Err(e) => {
+                problems.extend(file_problems(&global, ConfigError::Io(e)));
+                Config::default()
+            }
+        };
+
+        for file in project_files(dir).map_err(ConfigError::Io)? {
+            let input = match fs::read_to_string(&file) {
+                Ok(input) => input,
+                Err(e) => {
+                    problems.extend(file_problems(&file, ConfigError::Io(e)));
+                    continue;
+                }
+            };
+            match serde_yaml::from_str::<Project>(&input) {
+                Ok(mut project) => {
+                    project.source = Some(file.clone());
+                    config.projects.push(project);
+                }
+                Err(e) => problems.extend(file_problems(&file, ConfigError::Parse(e))),
+            }
+            inputs.insert(file, input);
+        }
+        if !problems.is_empty() {
+            return Err(ConfigError::Invalid(problems));
+        }
+
+        let mut problems = config.check(resolve);
+        if problems.is_empty() {
+            return Ok(config);
+        }
+        let mut positions = HashMap::new();
+        for problem in problems.iter_mut() {
+            // problems of a project file are reported relative to the project
+            let (file, path) = config
+                .projects
+                .iter()
+                .enumerate()
+                .find_map(|(index, project)| {
+                    let source = project.source.as_ref()?;
+                    let prefix = format!("projects[{}]", index);
+                    let rest = problem.path.strip_prefix(&prefix)?;
+                    match rest.strip_prefix('.') {
+                        Some(rest) => Some((source.clone(), rest.to_string())),
+                        None if rest.is_empty() => Some((source.clone(), String::new())),
+                        None => None,
+                    }
+                })
+                .unwrap_or_else(|| (global.clone(), problem.path.clone()));
+
+            let file_positions = positions.entry(file.clone()).or_insert_with(|| {
+                inputs
+                    .get(&file)
+                    .map(|input| locate::positions(input))
+                    .unwrap_or_default()
+            });
+            problem.position = file_positions.get(&path).copied();
+            problem.file = Some(file);
+            problem.path = path;
+        }
+        Err(ConfigError::Invalid(problems))
+    }
+
+    #[cfg(test)]
+    pub fn parse(input: &str) -> Result<Self, ConfigError> {
+        Self::parse_with(input, Secret::resolve)
+    }
+
+    /// Parse and validate a configuration, problems are located in `input`.
+    fn parse_with(input: &str, resolve: Resolve) -> Result<Self, ConfigError> {
+        let mut config = serde_yaml::from_str::<Config>(input).map_err(ConfigError::Parse)?;
+        let mut problems = config.check(resolve);
+        match problems.is_empty() {
+            true => Ok(config),
+            false => {
+                let positions = locate::positions(input);
+                for problem in problems.iter_mut() {
+                    problem.position = positions.get(&problem.path).copied();
+                }
+                Err(ConfigError::Invalid(problems))
+            }
+        }
+    }
+
+    /// Resolve the secrets and validate the configuration, secrets are resolved
+    /// first so they're validated too.
+    fn check(&mut self, resolve: Resolve) -> Vec<Problem> {
+        let mut problems = self.resolve(resolve);
+        if let Err(invalid) = self.validate() {
+            // a secret that can't be read is also empty
+            let unresolved = problems
+                .iter()
+                .map(|problem| problem.path.clone())
+                .collect::<Vec<String>>();
+            problems.extend(
+                invalid
+                    .into_iter()
+                    .filter(|problem| !unresolved.contains(&problem.path)),
+            );
+        }
+        problems
+    }
+
+    /// Read the secrets referenced by the configuration with `resolve`.
+    fn resolve(&mut self, resolve: Resolve) -> Vec<Problem> {
+        let mut problems = Vec::new();
+        if let Some(token) = self.admin_token.as_mut() {
+            if let Err(message) = resolve(token) {
+                problems.push(Problem::new("admin_token".to_string(), message));
+            }
+        }
+        for (index, project) in self.projects.iter_mut().enumerate() {
+            problems.extend(project.resolve(&format!("projects[{}]", index), resolve));
+        }
+        problems
+    }
+
+    /// Check the parts of the configuration that can't be expressed in its
+    /// types, every problem that's found is returned.
+    pub fn validate(&self) -> Result<(), Vec<Problem>> {
+        let mut problems = Vec::new();
+        let mut names = HashMap::new();
+
+        problems.extend(self.server.validate("server"));
+        problems.extend(self.queue.validate());
+        if self.projects.is_empty() {
+            problems.push(Problem::new(
+                "projects".to_string(),
+                "no projects are configured".to_string(),
+            ));
+        }
+
+        for (index, project) in self.projects.iter().enumerate() {
+            let path = format!("projects[{}]", index);
+            if project.name.trim().is_empty() {
+                problems.push(Problem::new(
+                    format!("{}.name", path),
+                    "project name is empty".to_string(),
+                ));
+            } else if project.name.contains('/') || project.name == "." || project.name == ".." {
+                // the name is a directory of the cache
+                problems.push(Problem::new(
+                    format!("{}.name", path),
+                    format!(
+                        "project name `{}` isn't a valid directory name",
+                        project.name
+                    ),
+                ));
+            } else if let Some(first) = names.insert(project.name.as_str(), index) {
+                problems.push(Problem::new(
+                    format!("{}.name", path),
+                    format!(
+                        "duplicate project name `{}`, it's already used by {}",
+                        project.name,
+                        self.describe(first)
+                    ),
+                ));
+            }
+            if project.token.is_empty() {
+                problems.push(Problem::new(
+                    format!("{}.token", path),
+                    "token is empty".to_string(),
+                ));
+            }
+            if project.commands.is_empty() {
+                problems.push(Problem::new(
+                    format!("{}.commands", path),
+                    "command list is empty".to_string(),
+                ));
+            }
+            if project.script {
+                // the script runs as a single step
+                for (command, _) in project
+                    .commands
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, c)| c.timeout().is_some())
+                {
+                    problems.push(Problem::new(
+                        format!("{}.commands[{}].timeout", path, command),
+                        "commands of a script can't have a timeout, use the project timeout"
+                            .to_string(),
+                    ));
+                }
+            }
+            for (index, rule) in project.rules.iter().flatten().enumerate() {
+                if rule.is_empty() {
+                    problems.push(Problem::new(
+                        format!("{}.rules[{}]", path, index),
+                        "rule has no conditions, it would deploy every event".to_string(),
+                    ));
+                }
+                for (field, message) in rule.invalid_patterns() {
+                    problems.push(Problem::new(
+                        format!("{}.rules[{}].{}", path, index, field),
+                        message,
+                    ));
+                }
+            }
+            problems.extend(project.git.validate(&format!("{}.git", path)));
+        }
+
+        match problems.is_empty() {
+            true => Ok(()),
+            false => Err(problems),
+        }
+    }
+
+    /// Values redacted from the command output of the project, its secrets and
+    /// the admin token.
+    fn secrets(&self, project: &Project) -> Vec<String> {
+        let mut secrets = project.secrets();
+        secrets.extend(
+            self.admin_token
+                .iter()
+                .map(|token| token.expose().to_string()),
+        );
+        secrets
+    }
+
+    /// Where the project at `index` is configured, for messages.
+    fn describe(&self, index: usize) -> String {
+        match &self.projects[index].source {
+            Some(source) => source.display().to_string(),
+            None => format!("projects[{}]", index),
+        }
+    }
+
     pub fn get_project(&self, project: String) -> Option<Project> {
         for item in &self.projects {
             if item.name.clone() == project {
@@ -56,52 +1073,237 @@ impl Config {
 
     /// Process the list of configured commands. There's lifetime issues if this
     /// is on the project, so it's here because the config is kept as app data
-    /// that's passed into handlers.
-    pub async fn execute_commands(&self, project: Project) {
+    /// that's passed into handlers. The event environment is added to the
+    /// project environment, and command output is streamed into the log and
+    /// the job record as it's produced.
+    ///
+    /// Processing stops at the first command that fails, unless it's set to
+    /// continue on error, and the job is marked as failed at that step. When
+    /// the command or the project timeout expires the process group of the
+    /// command is terminated and the job is marked as timed out.
+    ///
+    /// When `cancel` is cancelled the running command is terminated, the
+    /// remaining commands are skipped and the cleanup commands of the project
+    /// are run before the job is marked as cancelled.
+    ///
+    /// Commands run in the project working directory of the repository
+    /// `checkout`, the directory of the service process is never changed.
+    pub async fn execute_commands(
+        &self,
+        project: Project,
+        env: HashMap<String, String>,
+        checkout: Option<PathBuf>,
+        job: JobHandle,
+        cancel: Cancel,
+    ) {
         let log = slog_scope::logger();
+        let mut vars = project.env();
+        vars.extend(env);
+        let deadline = project.timeout().map(|timeout| Instant::now() + timeout);
+        let workdir = project.workdir(checkout.as_deref());
+        let secrets = self.secrets(&project);
+
+        debug!(log, "command processor";
+            "project_name" => project.name.clone(),
+            "workdir" => format!("{:?}", workdir),
+        );
+        for (index, command) in project.commands().iter().enumerate() {
+            if cancel.is_cancelled() {
+                warn!(log, "job cancelled, skipping remaining commands"; "project_name" => &project.name);
+                self.cleanup(&project, &vars, workdir.as_deref(), &job)
+                    .await;
+                job.lock().unwrap().cancel(None);
+                return;
+            }
+
+            let remaining =
+                deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+            let timeout = match (command.timeout(), remaining) {
+                (Some(timeout), Some(remaining)) => Some(timeout.min(remaining)),
+                (timeout, remaining) => timeout.or(remaining),
+            };
+
+            let outcome = Self::run_step(
+                command,
+                &vars,
+                workdir.as_deref(),
+                timeout,
+                &cancel,
+                &job,
+                &secrets,
+            )
+            .await;
+            let success = match outcome {
+                Ok(Outcome::Exited(status)) => status.success(),
+                Ok(Outcome::TimedOut) => {
+                    let expired = deadline.is_some_and(|deadline| Instant::now() >= deadline);
+                    if expired || !command.continue_on_error() {
+                        warn!(log, "command timed out, skipping remaining commands"; "project_name" => &project.name, "command" => command.run());
+                        job.lock().unwrap().time_out(index);
+                        return;
+                    }
+                    false
+                }
+                Ok(Outcome::Cancelled) => {
+                    warn!(log, "job cancelled, skipping remaining commands"; "project_name" => &project.name, "command" => command.run());
+                    self.cleanup(&project, &vars, workdir.as_deref(), &job)
+                        .await;
+                    job.lock().unwrap().cancel(Some(index));
+                    return;
+                }
+                Err(e) => {
+                    error!(log, "failed to execute command"; "command" => command.run(), "error" => e.to_string());
+                    false
+                }
+            };
+
+            if !success {
+                if command.continue_on_error() {
+                    warn!(log, "command failed, continuing"; "project_name" => &project.name, "command" => command.run());
+                    continue;
+                }
+                warn!(log, "command failed, skipping remaining commands"; "project_name" => &project.name, "command" => command.run());
+                job.lock().unwrap().fail(index);
+                return;
+            }
+        }
+
+        job.lock().unwrap().succeed();
+    }
 
-        debug!(log, "command processor"; "project_name" => project.name.clone());
-        for command in project.commands.iter() {
-            let output = Command::new("bash")
-                .arg("-c")
-                .arg(command)
-                .envs(project.env())
-                .output()
-                .expect("failed to execute command");
+    /// Run the cleanup commands of a cancelled job, failing commands don't
+    /// stop the others. Each command gets its own timeout, the project timeout
+    /// or `CLEANUP_TIMEOUT` unless it sets one.
+    async fn cleanup(
+        &self,
+        project: &Project,
+        vars: &HashMap<String, String>,
+        workdir: Option<&Path>,
+        job: &JobHandle,
+    ) {
+        let log = slog_scope::logger();
+        let secrets = self.secrets(project);
 
-            debug!(log, "processor"; "status" => format!("{:?}", output.status));
-            debug!(log, "processor"; "stdout" => str::from_utf8(&output.stdout).unwrap());
-            debug!(log, "processor"; "stderr" => str::from_utf8(&output.stderr).unwrap());
+        for command in project.cleanup.iter() {
+            let timeout = command.timeout().or(project.timeout());
+            let timeout = Some(timeout.unwrap_or(CLEANUP_TIMEOUT));
+            let cancel = Cancel::default();
+            match Self::run_step(command, vars, workdir, timeout, &cancel, job, &secrets).await {
+                Ok(Outcome::Exited(status)) if status.success() => {}
+                outcome => {
+                    warn!(log, "cleanup command failed"; "project_name" => &project.name, "command" => command.run(), "outcome" => format!("{:?}", outcome));
+                }
+            }
         }
     }
+
+    /// Run a command as a new step of the job, `secrets` are redacted from its
+    /// output.
+    async fn run_step(
+        command: &Command,
+        vars: &HashMap<String, String>,
+        workdir: Option<&Path>,
+        timeout: Option<Duration>,
+        cancel: &Cancel,
+        job: &JobHandle,
+        secrets: &[String],
+    ) -> std::io::Result<Outcome> {
+        let log = slog_scope::logger();
+
+        job.lock().unwrap().start_step(command.run());
+        let outcome = runner::run(
+            command.run(),
+            vars,
+            workdir,
+            timeout,
+            cancel,
+            |stream, line| {
+                let line = secret::redact(&line, secrets);
+                match stream {
+                    Stream::Stdout => debug!(log, "processor"; "stdout" => &line),
+                    Stream::Stderr => debug!(log, "processor"; "stderr" => &line),
+                }
+                job.lock().unwrap().push_line(stream, line);
+            },
+        )
+        .await;
+
+        let exit_code = match &outcome {
+            Ok(Outcome::Exited(status)) => {
+                debug!(log, "processor"; "status" => format!("{:?}", status));
+                status.code()
+            }
+            _ => None,
+        };
+        job.lock().unwrap().finish_step(exit_code);
+        outcome
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::job::{Job, Status};
+    use async_std::task;
+
+    fn event(kind: &str, branch: &str, action: &str, state: &str) -> Event {
+        Event {
+            kind: kind.to_string(),
+            branch: branch.to_string(),
+            action: action.to_string(),
+            state: state.to_string(),
+            ..Default::default()
+        }
+    }
 
     #[test]
     fn it_should_deploy() {
-        assert!(should_deploy(
-            "main".to_string(),
-            "merge".to_string(),
-            "merged".to_string()
-        ));
-        assert!(!should_deploy(
-            "staging".to_string(),
-            "merge".to_string(),
-            "merged".to_string()
-        ));
-        assert!(!should_deploy(
-            "main".to_string(),
-            "open".to_string(),
-            "merged".to_string()
-        ));
-        assert!(!should_deploy(
-            "main".to_string(),
-            "merge".to_string(),
-            "closed".to_string()
-        ));
+        let input = r#"
+          projects:
+            - name: sample
+              token: really-gud-secret
+              commands: []
+            - name: github-sample
+              token: github-secret-token
+              provider: github
+              commands: []
+        "#;
+        let config = serde_yaml::from_str::<Config>(input).unwrap();
+        let gitlab = &config.projects[0];
+        let github = &config.projects[1];
+
+        assert!(gitlab.should_deploy(&event("merge_request", "main", "merge", "merged")));
+        assert!(!gitlab.should_deploy(&event("merge_request", "staging", "merge", "merged")));
+        assert!(!gitlab.should_deploy(&event("merge_request", "main", "open", "merged")));
+        assert!(!gitlab.should_deploy(&event("merge_request", "main", "merge", "closed")));
+        assert!(github.should_deploy(&event("pull_request", "main", "closed", "merged")));
+        assert!(!github.should_deploy(&event("pull_request", "main", "opened", "open")));
+        assert!(!github.should_deploy(&event("pull_request", "main", "closed", "closed")));
+        assert!(!github.should_deploy(&event("pull_request", "develop", "closed", "merged")));
+    }
+
+    #[test]
+    fn it_deserializes_rules() {
+        let input = r#"
+          projects:
+            - name: sample
+              token: really-gud-secret
+              rules:
+                - branches: [master, "release/*"]
+                  actions: [merge]
+                  states: [merged]
+                - branches: [production]
+              commands:
+                - echo deploy
+        "#;
+        let config = serde_yaml::from_str::<Config>(input).unwrap();
+        let project = &config.projects[0];
+
+        assert_eq!(project.rules().len(), 2);
+        assert!(project.should_deploy(&event("merge_request", "master", "merge", "merged")));
+        assert!(project.should_deploy(&event("merge_request", "release/2.0", "merge", "merged")));
+        assert!(project.should_deploy(&event("merge_request", "production", "open", "")));
+        assert!(!project.should_deploy(&event("merge_request", "main", "merge", "merged")));
     }
 
     #[test]
@@ -121,13 +1323,897 @@ mod tests {
         let env = project.env();
 
         assert_eq!(project.name, "sample".to_string());
-        assert_eq!(project.token, "really-gud-secret".to_string());
+        assert_eq!(project.token.expose(), "really-gud-secret");
         assert_eq!(project.provider, Provider::GitLab); // default provider
+        assert!(!project.coalesce);
+        assert_eq!(config.queue, QueueSettings::default());
         assert_eq!(env.len(), 1);
+        assert_eq!(env.get("LOG"), Some(&"/tmp/sample.log".to_string()));
+    }
+
+    #[test]
+    fn it_deserializes_queue_settings() {
+        let input = r#"
+          queue:
+            concurrency: 2
+          projects:
+            - name: sample
+              token: really-gud-secret
+              coalesce: true
+              commands:
+                - echo deploy
+        "#;
+        let config = serde_yaml::from_str::<Config>(input).unwrap();
+
+        assert_eq!(config.queue.concurrency, 2);
+        assert_eq!(config.queue.capacity, 16);
+        assert!(config.projects[0].coalesce);
+    }
+
+    #[test]
+    fn it_deserializes_command_options() {
+        let input = r#"
+          projects:
+            - name: sample
+              token: really-gud-secret
+              commands:
+                - npm run build
+                - run: npm run lint
+                  continue_on_error: true
+        "#;
+        let config = serde_yaml::from_str::<Config>(input).unwrap();
+        let commands = &config.projects[0].commands;
+
+        assert_eq!(commands[0], Command::Shell("npm run build".to_string()));
+        assert!(!commands[0].continue_on_error());
+        assert_eq!(commands[1].run(), "npm run lint");
+        assert!(commands[1].continue_on_error());
+    }
+
+    fn run_commands(commands: &str) -> Job {
+        run_project(&format!("commands: {}", commands))
+    }
+
+    fn run_project(settings: &str) -> Job {
+        run_cancelled_project(settings, Cancel::default())
+    }
+
+    fn run_cancelled_project(settings: &str, cancel: Cancel) -> Job {
+        let input = format!(
+            "projects:\n  - name: sample\n    token: secret\n    {}\n",
+            settings
+        );
+        let config = serde_yaml::from_str::<Config>(&input).unwrap();
+        let project = config.get_project("sample".to_string()).unwrap();
+        let job = Job::new("sample").handle();
+
+        task::block_on(config.execute_commands(project, HashMap::new(), None, job.clone(), cancel));
+        let record = job.lock().unwrap().clone();
+        record
+    }
+
+    #[test]
+    fn it_stops_on_failure() {
+        let job = run_commands(r#"["echo one", "exit 2", "echo three"]"#);
+
+        assert_eq!(job.status, Status::Failed);
+        assert_eq!(job.failed_step, Some(1));
+        assert_eq!(job.steps.len(), 2);
+        assert_eq!(job.steps[1].exit_code, Some(2));
+    }
+
+    #[test]
+    fn it_continues_on_error() {
+        let job =
+            run_commands(r#"["echo one", {run: "exit 2", continue_on_error: true}, "echo three"]"#);
+
+        assert_eq!(job.status, Status::Succeeded);
+        assert_eq!(job.failed_step, None);
+        assert_eq!(job.steps.len(), 3);
+        assert_eq!(job.steps[1].exit_code, Some(2));
+    }
+
+    #[test]
+    fn it_times_out_commands() {
+        let job = run_commands(
+            r#"[{run: "sleep 5", timeout: 1, continue_on_error: true}, {run: "sleep 5", timeout: 1}, "echo three"]"#,
+        );
+
+        assert_eq!(job.status, Status::TimedOut);
+        assert_eq!(job.failed_step, Some(1));
+        assert_eq!(job.steps.len(), 2);
+        assert_eq!(job.steps[1].exit_code, None);
+    }
+
+    #[test]
+    fn it_times_out_jobs() {
+        let start = Instant::now();
+        let job = run_project(
+            r#"timeout: 1
+    commands: [{run: "sleep 5", continue_on_error: true}, "echo two"]"#,
+        );
+
+        assert_eq!(job.status, Status::TimedOut);
+        assert_eq!(job.failed_step, Some(0));
+        assert!(start.elapsed() < Duration::from_secs(4));
+    }
+
+    #[test]
+    fn it_cancels_jobs() {
+        let cancel = Cancel::default();
+        let canceller = cancel.clone();
+        task::spawn(async move {
+            task::sleep(Duration::from_millis(300)).await;
+            canceller.cancel();
+        });
+
+        let job = run_cancelled_project(
+            r#"commands: ["echo one", "sleep 5", "echo three"]
+    cleanup: ["echo cleaning up"]"#,
+            cancel,
+        );
+
+        assert_eq!(job.status, Status::Cancelled);
+        assert_eq!(job.failed_step, Some(1));
+        assert_eq!(job.steps.len(), 3);
+        assert_eq!(job.steps[1].exit_code, None);
+        assert_eq!(job.steps[2].command, "echo cleaning up".to_string());
+        assert_eq!(job.steps[2].output[0].text, "cleaning up".to_string());
+    }
+
+    #[test]
+    fn it_times_out_cleanup_commands() {
+        let cancel = Cancel::default();
+        let canceller = cancel.clone();
+        task::spawn(async move {
+            task::sleep(Duration::from_millis(300)).await;
+            canceller.cancel();
+        });
+
+        let start = Instant::now();
+        let job = run_cancelled_project(
+            r#"timeout: 1
+    commands: ["sleep 5"]
+    cleanup: ["sleep 5", "echo cleaning up"]"#,
+            cancel,
+        );
+
+        assert_eq!(job.status, Status::Cancelled);
+        assert_eq!(job.steps.len(), 3);
+        assert_eq!(job.steps[2].output[0].text, "cleaning up".to_string());
+        assert!(start.elapsed() < Duration::from_secs(4));
+    }
+
+    #[test]
+    fn it_skips_commands_of_cancelled_jobs() {
+        let cancel = Cancel::default();
+        cancel.cancel();
+
+        let job = run_cancelled_project(r#"commands: ["echo one"]"#, cancel);
+
+        assert_eq!(job.status, Status::Cancelled);
+        assert_eq!(job.failed_step, None);
+        assert!(job.steps.is_empty());
+    }
+
+    #[test]
+    fn it_resolves_workdir() {
+        let input = r#"
+          projects:
+            - name: relative
+              token: secret
+              workdir: app
+              commands: []
+            - name: absolute
+              token: secret
+              workdir: /opt/app
+              commands: []
+            - name: checkout
+              token: secret
+              commands: []
+        "#;
+        let config = serde_yaml::from_str::<Config>(input).unwrap();
+        let checkout = Path::new("/var/cache/shook/repo");
+        let workdir = |name: &str, checkout| {
+            config
+                .get_project(name.to_string())
+                .unwrap()
+                .workdir(checkout)
+        };
+
+        assert_eq!(
+            workdir("relative", Some(checkout)),
+            Some(PathBuf::from("/var/cache/shook/repo/app"))
+        );
+        assert_eq!(
+            workdir("absolute", Some(checkout)),
+            Some(PathBuf::from("/opt/app"))
+        );
+        assert_eq!(
+            workdir("checkout", Some(checkout)),
+            Some(PathBuf::from("/var/cache/shook/repo"))
+        );
+        assert_eq!(workdir("checkout", None), None);
+    }
+
+    #[test]
+    fn it_runs_commands_in_checkout() {
+        let checkout = std::env::temp_dir().canonicalize().unwrap();
+        let input = "projects:\n  - name: sample\n    token: secret\n    commands: [pwd]\n";
+        let config = serde_yaml::from_str::<Config>(input).unwrap();
+        let project = config.get_project("sample".to_string()).unwrap();
+        let job = Job::new("sample").handle();
+
+        task::block_on(config.execute_commands(
+            project,
+            HashMap::new(),
+            Some(checkout.clone()),
+            job.clone(),
+            Cancel::default(),
+        ));
+
+        let job = job.lock().unwrap();
+        assert_eq!(job.steps[0].output[0].text, checkout.display().to_string());
+    }
+
+    #[test]
+    fn it_runs_script_in_one_shell() {
+        let job = run_project(
+            r#"script: true
+    commands:
+      - cd /
+      - export GREETING=hello
+      - {run: "false", continue_on_error: true}
+      - pwd
+      - echo $GREETING
+      - exit 4
+      - echo unreachable"#,
+        );
+        let output = job.steps[0]
+            .output
+            .iter()
+            .map(|line| line.text.clone())
+            .collect::<Vec<String>>();
+
+        assert_eq!(job.steps.len(), 1);
+        assert_eq!(job.status, Status::Failed);
+        assert_eq!(job.steps[0].exit_code, Some(4));
+        assert_eq!(output, vec!["/".to_string(), "hello".to_string()]);
+    }
+
+    #[test]
+    fn it_rejects_command_timeouts_in_scripts() {
+        let input = "projects:
+  - name: sample
+    token: secret
+    script: true
+    timeout: 600
+    commands:
+      - make
+      - run: make deploy
+        timeout: 60
+";
+        let problems = match Config::parse(input) {
+            Err(ConfigError::Invalid(problems)) => problems,
+            _ => panic!("expected the configuration to be invalid"),
+        };
+        let problems = problems
+            .iter()
+            .map(Problem::to_string)
+            .collect::<Vec<String>>();
+        assert_eq!(
+            problems,
+            vec!["9:9: projects[0].commands[1].timeout: commands of a script can't have a timeout, use the project timeout"]
+        );
+    }
+
+    #[test]
+    fn it_reports_invalid_projects() {
+        let input = "projects:
+  - name: sample
+    token: secret
+    commands: [make]
+  - name: sample
+    token: ''
+    commands: []
+  - name: ../other
+    token: secret
+    commands: [make]
+";
+
+        let problems = match Config::parse(input) {
+            Err(ConfigError::Invalid(problems)) => problems,
+            _ => panic!("expected the configuration to be invalid"),
+        };
+        let problems = problems
+            .iter()
+            .map(Problem::to_string)
+            .collect::<Vec<String>>();
+        assert_eq!(
+            problems,
+            vec![
+                "5:5: projects[1].name: duplicate project name `sample`, it's already used by projects[0]",
+                "6:5: projects[1].token: token is empty",
+                "7:5: projects[1].commands: command list is empty",
+                "8:5: projects[2].name: project name `../other` isn't a valid directory name",
+            ]
+        );
+    }
+
+    #[test]
+    fn it_reports_invalid_command_options() {
+        for (options, message) in &[
+            (
+                "{run: make, continu_on_error: true}",
+                "unknown field `continu_on_error`",
+            ),
+            ("{run: make, timeout: ten}", "invalid type: string \"ten\""),
+            ("{timeout: 10}", "missing field `run`"),
+        ] {
+            let input = format!(
+                "projects:\n  - name: sample\n    token: secret\n    commands:\n      - {}\n",
+                options
+            );
+            let problems = match Config::parse(&input) {
+                Err(e @ ConfigError::Parse(_)) => e.problems(),
+                _ => panic!("expected the configuration to fail to parse"),
+            };
+            assert!(
+                problems[0].message.contains(message),
+                "{}",
+                problems[0].message
+            );
+        }
+    }
+
+    #[test]
+    fn it_rejects_invalid_rules() {
+        let input = "projects:
+  - name: sample
+    token: secret
+    rules:
+      - branchs: [main]
+    commands: [make]
+";
+        let problems = match Config::parse(input) {
+            Err(e @ ConfigError::Parse(_)) => e.problems(),
+            _ => panic!("expected the configuration to fail to parse"),
+        };
+        assert!(problems[0].message.contains("unknown field `branchs`"));
+
+        let input = "projects:
+  - name: sample
+    token: secret
+    rules:
+      - events: [push]
+      - {}
+    commands: [make]
+";
+        let problems = match Config::parse(input) {
+            Err(ConfigError::Invalid(problems)) => problems,
+            _ => panic!("expected the configuration to be invalid"),
+        };
+        assert_eq!(
+            problems[0].to_string(),
+            "6:9: projects[0].rules[1]: rule has no conditions, it would deploy every event"
+        );
+
+        let input = "projects:
+  - name: sample
+    token: secret
+    rules:
+      - branches: [main, \"release/[0-9\"]
+    commands: [make]
+";
+        let problems = match Config::parse(input) {
+            Err(ConfigError::Invalid(problems)) => problems,
+            _ => panic!("expected the configuration to be invalid"),
+        };
+        assert_eq!(problems[0].path, "projects[0].rules[0].branches[1]");
+        assert_eq!(problems[0].position, Some((5, 26)));
+        assert!(problems[0]
+            .message
+            .contains("`release/[0-9` isn't a valid glob pattern"));
+    }
+
+    #[test]
+    fn it_rejects_unknown_fields() {
+        let input = "projects:\n  - name: sample\n    token: secret\n    comands: [make]\n";
+
+        let problems = match Config::parse(input) {
+            Err(e @ ConfigError::Parse(_)) => e.problems(),
+            _ => panic!("expected the configuration to fail to parse"),
+        };
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].message.contains("unknown field `comands`"));
+        assert_eq!(problems[0].position, Some((4, 5)));
+    }
+
+    #[test]
+    fn it_resolves_secrets() {
+        std::env::set_var("SHOOK_CONFIG_SECRET", "hunter2");
+        let input = "admin_token: ${env:SHOOK_CONFIG_SECRET}
+projects:
+  - name: sample
+    token: ${env:SHOOK_CONFIG_SECRET}
+    env:
+      PASSWORD: ${env:SHOOK_CONFIG_SECRET}
+    commands: ['echo password is $PASSWORD']
+";
+        let config = Config::parse(input).unwrap();
+        assert_eq!(config.admin_token.as_ref().unwrap().expose(), "hunter2");
+        let project = config.get_project("sample".to_string()).unwrap();
+        assert_eq!(project.token.expose(), "hunter2");
+        assert_eq!(project.env()["PASSWORD"], "hunter2");
+
+        let job = Job::new("sample").handle();
+        task::block_on(config.execute_commands(
+            project,
+            HashMap::new(),
+            None,
+            job.clone(),
+            Cancel::default(),
+        ));
+        let job = job.lock().unwrap();
+        assert_eq!(job.steps[0].output[0].text, "password is [redacted]");
+    }
+
+    #[test]
+    fn it_redacts_plain_secrets() {
+        let input = "admin_token: admin-secret
+projects:
+  - name: sample
+    token: project-secret
+    env:
+      PASSWORD: env-secret
+    commands: ['echo $PASSWORD project-secret admin-secret']
+";
+        let config = Config::parse(input).unwrap();
+        let project = config.get_project("sample".to_string()).unwrap();
+
+        let job = Job::new("sample").handle();
+        task::block_on(config.execute_commands(
+            project,
+            HashMap::new(),
+            None,
+            job.clone(),
+            Cancel::default(),
+        ));
+        let job = job.lock().unwrap();
+        assert_eq!(
+            job.steps[0].output[0].text,
+            "[redacted] [redacted] [redacted]"
+        );
+    }
+
+    #[test]
+    fn it_reads_git_settings() {
+        std::env::set_var("SHOOK_CONFIG_GIT_TOKEN", "glpat-123");
+        let input = "projects:
+  - name: plain
+    token: secret
+    commands: [make]
+  - name: key
+    token: secret
+    git:
+      repository: git@gitlab.com:group/key.git
+      ssh_key: /etc/shook/id_ed25519
+      passphrase: hunter2
+      known_hosts: /etc/shook/known_hosts
+    commands: [make]
+  - name: agent
+    provider: github
+    token: secret
+    git:
+      repository: git@github.com:org/agent.git
+      ssh_agent: true
+    commands: [make]
+  - name: token
+    provider: github
+    token: secret
+    git:
+      repository: https://github.com/org/token.git
+      token: ${env:SHOOK_CONFIG_GIT_TOKEN}
+    commands: [make]
+  - name: deploy-token
+    token: secret
+    git:
+      repository: https://gitlab.com/group/deploy-token.git
+      username: gitlab+deploy-token-1
+      token: ${env:SHOOK_CONFIG_GIT_TOKEN}
+    commands: [make]
+";
+        let config = Config::parse(input).unwrap();
+        let project = |name: &str| config.get_project(name.to_string()).unwrap();
+
+        assert_eq!(project("plain").git.protocol(), Protocol::Https);
+        assert_eq!(project("plain").credentials(), Credentials::None);
+        assert_eq!(project("key").git.protocol(), Protocol::Ssh);
+        assert_eq!(
+            project("key").credentials(),
+            Credentials::SshKey {
+                path: PathBuf::from("/etc/shook/id_ed25519"),
+                passphrase: Some("hunter2".to_string()),
+                known_hosts: PathBuf::from("/etc/shook/known_hosts"),
+            }
+        );
+        assert_eq!(project("agent").git.protocol(), Protocol::Ssh);
+        let home = PathBuf::from(std::env::var_os("HOME").unwrap_or_default());
         assert_eq!(
-            env.get(&"LOG".to_string()),
-            Some(&"/tmp/sample.log".to_string())
+            project("agent").credentials(),
+            Credentials::SshAgent {
+                known_hosts: home.join(".ssh/known_hosts"),
+            }
+        );
+        assert_eq!(project("token").git.protocol(), Protocol::Https);
+        assert_eq!(
+            project("token").credentials(),
+            Credentials::Token {
+                username: "x-access-token".to_string(),
+                token: "glpat-123".to_string(),
+            }
         );
+        assert_eq!(
+            project("deploy-token").credentials(),
+            Credentials::Token {
+                username: "gitlab+deploy-token-1".to_string(),
+                token: "glpat-123".to_string(),
+            }
+        );
+        assert_eq!(
+            project("deploy-token").secrets(),
+            vec!["secret", "glpat-123"]
+        );
+    }
+
+    #[test]
+    fn it_rejects_invalid_git_settings() {
+        let input = "projects:
+  - name: sample
+    token: secret
+    git:
+      repository: git@gitlab.com:group/sample.git
+      ssh_key: /etc/shook/id_ed25519
+      ssh_agent: true
+    commands: [make]
+  - name: other
+    token: secret
+    git:
+      repository: git@gitlab.com:group/other.git
+      protocol: ssh
+      passphrase: hunter2
+      token: ''
+    commands: [make]
+  - name: unpinned
+    token: secret
+    git:
+      ssh_agent: true
+    commands: [make]
+  - name: mismatched
+    token: secret
+    git:
+      repository: https://gitlab.com/group/mismatched.git
+      protocol: ssh
+      known_hosts: /etc/shook/known_hosts
+    commands: [make]
+  - name: local
+    token: secret
+    git:
+      repository: /srv/git/local.git
+    commands: [make]
+";
+        let problems = match Config::parse(input) {
+            Err(ConfigError::Invalid(problems)) => problems,
+            _ => panic!("expected the configuration to be invalid"),
+        };
+        let problems = problems
+            .iter()
+            .map(Problem::to_string)
+            .collect::<Vec<String>>();
+        assert_eq!(
+            problems,
+            vec![
+                "4:5: projects[0].git: only one of ssh_key, ssh_agent and token can be set",
+                "13:7: projects[1].git.protocol: token requires the https protocol",
+                "14:7: projects[1].git.passphrase: passphrase requires ssh_key",
+                "15:7: projects[1].git.token: token is empty",
+                "19:5: projects[2].git: repository is required with credentials, they're only sent to it",
+                "26:7: projects[3].git.protocol: protocol doesn't match the repository URL",
+                "27:7: projects[3].git.known_hosts: known_hosts requires ssh_key or ssh_agent",
+                "32:7: projects[4].git.repository: `/srv/git/local.git` isn't a clone URL with a repository path",
+            ]
+        );
+    }
+
+    #[test]
+    fn it_reports_unresolved_secrets() {
+        let input = "projects:
+  - name: sample
+    token: ${env:SHOOK_CONFIG_MISSING}
+    env:
+      KEY: file:/nonexistent/shook/secret
+    commands: [make]
+";
+        let problems = match Config::parse(input) {
+            Err(ConfigError::Invalid(problems)) => problems,
+            _ => panic!("expected the configuration to be invalid"),
+        };
+        let paths = problems
+            .iter()
+            .map(|problem| (problem.path.as_str(), problem.position))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            vec![
+                ("projects[0].token", Some((3, 5))),
+                ("projects[0].env.KEY", Some((5, 7))),
+            ]
+        );
+    }
+
+    #[test]
+    fn it_checks_secrets_without_reading_them() {
+        let dir = config_dir(
+            "conf-unresolved",
+            &[(
+                "sample.yml",
+                "name: sample
+token: ${env:SHOOK_CONFIG_MISSING}
+env:
+  KEY: file:/nonexistent/shook/secret
+commands: [make]
+",
+            )],
+        );
+        assert!(Config::load(&dir).is_err());
+        assert!(Config::load_unresolved(&dir).is_ok());
+
+        fs::write(
+            dir.join("sample.yml"),
+            "name: sample\ntoken: ${env:SHOOK_CONFIG_MISSING\ncommands: [make]\n",
+        )
+        .unwrap();
+        let problems = match Config::load_unresolved(&dir) {
+            Err(ConfigError::Invalid(problems)) => problems,
+            _ => panic!("expected the configuration to be invalid"),
+        };
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].path, "token");
+        assert_eq!(problems[0].position, Some((2, 1)));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn config_dir(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("shook-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        for (file, input) in files {
+            fs::write(dir.join(file), input).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn it_loads_configuration_directories() {
+        let dir = config_dir(
+            "conf-d",
+            &[
+                (
+                    GLOBAL_FILE,
+                    "admin_token: admin\nqueue:\n  concurrency: 2\n",
+                ),
+                ("b.yml", "name: second\ntoken: two\ncommands: [make]\n"),
+                ("a.yaml", "name: first\ntoken: one\ncommands: [make]\n"),
+                (".hidden.yml", "not: a project\n"),
+                ("notes.txt", "not a project"),
+            ],
+        );
+
+        let config = Config::load(&dir).unwrap();
+        assert_eq!(config.admin_token.unwrap().expose(), "admin");
+        assert_eq!(config.queue.concurrency, 2);
+        let names = config
+            .projects
+            .iter()
+            .map(|project| project.name.as_str())
+            .collect::<Vec<&str>>();
+        assert_eq!(names, vec!["first", "second"]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn it_reports_problems_of_project_files() {
+        let dir = config_dir(
+            "conf-d-invalid",
+            &[
+                ("a.yml", "name: sample\ntoken: one\ncommands: [make]\n"),
+                ("b.yml", "name: sample\ntoken: ''\ncommands: [make]\n"),
+            ],
+        );
+
+        let problems = match Config::load(&dir) {
+            Err(ConfigError::Invalid(problems)) => problems,
+            _ => panic!("expected the configuration to be invalid"),
+        };
+        let problems = problems
+            .iter()
+            .map(Problem::to_string)
+            .collect::<Vec<String>>();
+        let a = dir.join("a.yml").display().to_string();
+        let b = dir.join("b.yml").display().to_string();
+        assert_eq!(
+            problems,
+            vec![
+                format!(
+                    "{}:1:1: name: duplicate project name `sample`, it's already used by {}",
+                    b, a
+                ),
+                format!("{}:2:1: token: token is empty", b),
+            ]
+        );
+
+        fs::write(dir.join("b.yml"), "name: other\ntokn: two\n").unwrap();
+        let problems = match Config::load(&dir) {
+            Err(e) => e.problems(),
+            Ok(_) => panic!("expected the configuration to fail to parse"),
+        };
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].file, Some(dir.join("b.yml")));
+        assert!(problems[0].message.contains("unknown field `tokn`"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn it_reads_server_settings() {
+        let input = "server:
+  host: 127.0.0.1
+  port: 8080
+  log_level: debug
+  cache_dir: /srv/shook
+  payload_limit: 1048576
+  workers: 2
+  tls:
+    cert: /etc/shook/cert.pem
+    key: /etc/shook/key.pem
+projects:
+  - name: sample
+    token: secret
+    commands: [make]
+";
+        let server = Config::parse(input).unwrap().server;
+        assert_eq!(server.host(), "127.0.0.1");
+        assert_eq!(server.port(), 8080);
+        assert_eq!(server.log_level(), slog::Level::Debug);
+        assert_eq!(server.cache_dir(), PathBuf::from("/srv/shook"));
+        assert_eq!(server.payload_limit(), 1_048_576);
+        assert_eq!(server.workers, Some(2));
+        assert_eq!(
+            server.tls.unwrap().cert,
+            PathBuf::from("/etc/shook/cert.pem")
+        );
+
+        let defaults = ServerSettings::default();
+        assert_eq!(defaults.host(), "0.0.0.0");
+        assert_eq!(defaults.port(), 5000);
+        assert_eq!(defaults.cache_dir(), PathBuf::from("/var/cache/shook"));
+        assert_eq!(defaults.payload_limit(), 262_144);
+    }
+
+    #[test]
+    fn it_rejects_invalid_server_settings() {
+        let input = "server:\n  log_level: loud\nprojects: []\n";
+        assert!(matches!(Config::parse(input), Err(ConfigError::Parse(_))));
+
+        let input = "server:
+  workers: 0
+projects:
+  - name: sample
+    token: secret
+    commands: [make]
+";
+        let problems = match Config::parse(input) {
+            Err(ConfigError::Invalid(problems)) => problems,
+            _ => panic!("expected the configuration to be invalid"),
+        };
+        assert_eq!(problems[0].path, "server.workers");
+        assert_eq!(problems[0].position, Some((2, 3)));
+    }
+
+    #[test]
+    fn it_rejects_empty_queues() {
+        let input = "queue:
+  concurrency: 0
+  capacity: 0
+projects:
+  - name: sample
+    token: secret
+    commands: [make]
+";
+        let problems = match Config::parse(input) {
+            Err(ConfigError::Invalid(problems)) => problems,
+            _ => panic!("expected the configuration to be invalid"),
+        };
+        assert_eq!(problems.len(), 2);
+        assert_eq!(problems[0].path, "queue.concurrency");
+        assert_eq!(problems[0].position, Some((2, 3)));
+        assert_eq!(problems[1].path, "queue.capacity");
+        assert_eq!(problems[1].position, Some((3, 3)));
+    }
+
+    #[test]
+    fn it_reads_server_settings_from_variables() {
+        let vars = |vars: &'static [(&'static str, &'static str)]| {
+            move |name: &str| {
+                vars.iter()
+                    .find(|(key, _)| *key == name)
+                    .map(|(_, value)| value.to_string())
+            }
+        };
+
+        let settings = ServerSettings::from_vars(vars(&[
+            ("SHOOK_PORT", "8080"),
+            ("SHOOK_LOG_LEVEL", "TRACE"),
+            ("SHOOK_CACHE_DIR", "/srv/shook"),
+        ]))
+        .unwrap();
+        assert_eq!(settings.port, Some(8080));
+        assert_eq!(settings.log_level, Some(slog::Level::Trace));
+        assert_eq!(settings.cache_dir, Some(PathBuf::from("/srv/shook")));
+        assert_eq!(settings.host, None);
+
+        let problems = ServerSettings::from_vars(vars(&[
+            ("SHOOK_PORT", "http"),
+            ("SHOOK_TLS_KEY", "/etc/shook/key.pem"),
+            ("SHOOK_WORKERS", "0"),
+        ]))
+        .unwrap_err();
+        let paths = problems
+            .iter()
+            .map(|problem| problem.path.as_str())
+            .collect::<Vec<&str>>();
+        assert_eq!(paths, vec!["SHOOK_TLS_CERT", "SHOOK_PORT", "SHOOK_WORKERS"]);
+    }
+
+    #[test]
+    fn it_prefers_earlier_server_settings() {
+        let cli = ServerSettings {
+            port: Some(1),
+            ..Default::default()
+        };
+        let env = ServerSettings {
+            port: Some(2),
+            host: Some("127.0.0.1".to_string()),
+            ..Default::default()
+        };
+        let file = ServerSettings {
+            port: Some(3),
+            host: Some("10.0.0.1".to_string()),
+            workers: Some(4),
+            ..Default::default()
+        };
+
+        let settings = cli.or(env).or(file);
+        assert_eq!(settings.port(), 1);
+        assert_eq!(settings.host(), "127.0.0.1");
+        assert_eq!(settings.workers, Some(4));
+        assert_eq!(settings.payload_limit(), 262_144);
+    }
+
+    #[test]
+    fn it_replaces_shared_config() {
+        let input = "projects:\n  - name: sample\n    token: secret\n    commands: []\n";
+        let shared = SharedConfig::new(serde_yaml::from_str::<Config>(input).unwrap());
+        let snapshot = shared.get();
+
+        let input = "projects:\n  - name: other\n    token: secret\n    commands: []\n";
+        shared.replace(serde_yaml::from_str::<Config>(input).unwrap());
+
+        assert!(snapshot.get_project("sample".to_string()).is_some());
+        assert!(shared.get().get_project("sample".to_string()).is_none());
+        assert!(shared.get().get_project("other".to_string()).is_some());
     }
 
     #[test]
@@ -147,7 +2233,7 @@ mod tests {
         let project = &config.projects[0];
 
         assert_eq!(project.name, "github-project".to_string());
-        assert_eq!(project.token, "github-secret-token".to_string());
+        assert_eq!(project.token.expose(), "github-secret-token");
         assert_eq!(project.provider, Provider::GitHub);
     }
 }
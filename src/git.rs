@@ -0,0 +1,834 @@
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::cell::Cell;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Error of the git operations that update a repository checkout.
+#[derive(Debug)]
+pub enum GitError {
+    /// The directory of the checkout couldn't be created.
+    Io(io::Error),
+    /// The repository path would leave the checkout root.
+    Path(String),
+    /// The event names another repository than the configured one.
+    Repository(String),
+    Open(git2::Error),
+    Clone(git2::Error),
+    /// The origin of the checkout couldn't be pointed at the repository.
+    Remote(git2::Error),
+    /// The SSH host key of the host isn't in the known hosts file.
+    HostKey(String),
+    /// The default branch of the remote couldn't be read.
+    DefaultBranch(git2::Error),
+    Reset(git2::Error),
+    Fetch {
+        reference: String,
+        error: git2::Error,
+    },
+    /// The local branch has diverged from the fetched one.
+    NotFastForward {
+        branch: String,
+    },
+    Checkout {
+        reference: String,
+        error: git2::Error,
+    },
+}
+
+impl fmt::Display for GitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GitError::Io(e) => write!(f, "failed to create the checkout directory: {}", e),
+            GitError::Path(path) => write!(f, "invalid repository path {}", path),
+            GitError::Repository(url) => {
+                write!(f, "repository {} isn't the configured one", url)
+            }
+            GitError::Open(e) => write!(f, "failed to open repository: {}", e),
+            GitError::Clone(e) => write!(f, "failed to clone repository: {}", e),
+            GitError::Remote(e) => write!(f, "failed to set the repository URL: {}", e),
+            GitError::HostKey(host) => write!(f, "host key of {} isn't a known host key", host),
+            GitError::DefaultBranch(e) => write!(f, "failed to read the default branch: {}", e),
+            GitError::Reset(e) => write!(f, "failed to reset repository: {}", e),
+            GitError::Fetch { reference, error } => {
+                write!(f, "failed to fetch {}: {}", reference, error)
+            }
+            GitError::NotFastForward { branch } => {
+                write!(f, "branch {} can't be fast-forwarded", branch)
+            }
+            GitError::Checkout { reference, error } => {
+                write!(f, "failed to check out {}: {}", reference, error)
+            }
+        }
+    }
+}
+
+impl std::error::Error for GitError {}
+
+impl From<io::Error> for GitError {
+    fn from(e: io::Error) -> Self {
+        GitError::Io(e)
+    }
+}
+
+/// Reference of the repository a deployment checks out.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Ref {
+    Branch(String),
+    Tag(String),
+    /// No reference, the commit of the source is looked up in every branch.
+    Commit,
+}
+
+/// Protocol repositories are cloned and fetched with.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    #[default]
+    Https,
+    Ssh,
+}
+
+impl Protocol {
+    /// Protocol of a clone URL, anything but an HTTP URL is taken for SSH.
+    pub fn of(url: &str) -> Protocol {
+        match url.starts_with("https://") || url.starts_with("http://") {
+            true => Protocol::Https,
+            false => Protocol::Ssh,
+        }
+    }
+}
+
+/// Credentials repositories are cloned and fetched with.
+#[derive(Clone, Default, PartialEq)]
+pub enum Credentials {
+    /// Anonymous access, or whatever libgit2 finds on its own.
+    #[default]
+    None,
+    /// Private key file, with the passphrase it's encrypted with. The host
+    /// key of the server has to be in the known hosts file.
+    SshKey {
+        path: PathBuf,
+        passphrase: Option<String>,
+        known_hosts: PathBuf,
+    },
+    /// Keys of the running ssh-agent.
+    SshAgent { known_hosts: PathBuf },
+    /// HTTPS username and access token, such as a GitLab deploy token.
+    Token { username: String, token: String },
+}
+
+impl fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Credentials::None => write!(f, "None"),
+            Credentials::SshKey { path, .. } => write!(f, "SshKey({})", path.display()),
+            Credentials::SshAgent { .. } => write!(f, "SshAgent"),
+            Credentials::Token { username, .. } => write!(f, "Token({})", username),
+        }
+    }
+}
+
+impl Credentials {
+    /// Known hosts file the SSH host keys are checked against.
+    fn known_hosts(&self) -> Option<&Path> {
+        match self {
+            Credentials::SshKey { known_hosts, .. } | Credentials::SshAgent { known_hosts } => {
+                Some(known_hosts)
+            }
+            _ => None,
+        }
+    }
+
+    /// Options that authenticate clones and fetches with the credentials. The
+    /// host of an SSH host key that isn't known is set in `rejected`.
+    fn fetch_options<'a>(&'a self, rejected: &'a Cell<Option<String>>) -> git2::FetchOptions<'a> {
+        let mut options = git2::FetchOptions::new();
+        if *self != Credentials::None {
+            options.remote_callbacks(self.callbacks(rejected));
+        }
+        options
+    }
+
+    /// Callbacks that authenticate connections to remotes with the
+    /// credentials, see `fetch_options`.
+    fn callbacks<'a>(&'a self, rejected: &'a Cell<Option<String>>) -> git2::RemoteCallbacks<'a> {
+        let mut callbacks = git2::RemoteCallbacks::new();
+        // only set for SSH, git2 doesn't tell the callback whether libgit2
+        // found an HTTPS certificate valid
+        if let Some(known_hosts) = self.known_hosts() {
+            callbacks.certificate_check(move |cert, host| {
+                let known = cert
+                    .as_hostkey()
+                    .and_then(|key| key.hash_sha256())
+                    .is_some_and(|hash| known_host(known_hosts, host, hash));
+                if !known {
+                    rejected.set(Some(host.to_string()));
+                }
+                known
+            });
+        }
+        let mut attempted = false;
+        callbacks.credentials(move |_url, username, _allowed| {
+            // libgit2 asks again as long as the credentials are rejected
+            if attempted {
+                return Err(git2::Error::from_str(
+                    "authentication failed with the configured credentials",
+                ));
+            }
+            attempted = true;
+            let username = username.unwrap_or("git");
+            match self {
+                Credentials::None => git2::Cred::default(),
+                Credentials::SshKey {
+                    path, passphrase, ..
+                } => git2::Cred::ssh_key(username, None, path, passphrase.as_deref()),
+                Credentials::SshAgent { .. } => git2::Cred::ssh_key_from_agent(username),
+                Credentials::Token { username, token } => {
+                    git2::Cred::userpass_plaintext(username, token)
+                }
+            }
+        });
+        callbacks
+    }
+}
+
+/// Whether `hash` is the SHA-256 hash of a key of `host` in the known hosts
+/// file at `path`. Hashed host names and markers aren't supported, and the
+/// port of a `[host]:port` entry isn't compared.
+fn known_host(path: &Path, host: &str, hash: &[u8; 32]) -> bool {
+    let hosts = match fs::read_to_string(path) {
+        Ok(hosts) => hosts,
+        Err(_) => return false,
+    };
+    hosts.lines().any(|line| {
+        let mut fields = line.split_whitespace();
+        let (names, key) = match (fields.next(), fields.next(), fields.next()) {
+            (Some(names), Some(_), Some(key)) => (names, key),
+            _ => return false,
+        };
+        let named = names.split(',').any(|name| {
+            let name = name
+                .strip_prefix('[')
+                .and_then(|name| name.split_once("]:"))
+                .map_or(name, |(name, _)| name);
+            name == host
+        });
+        named && base64::decode(key).is_ok_and(|key| Sha256::digest(key).as_slice() == hash)
+    })
+}
+
+/// Repository revision an event deploys, built by the providers from their
+/// payloads.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Source {
+    /// HTTPS clone URL of the repository.
+    pub url: String,
+    /// SSH clone URL, when the event has one.
+    pub ssh_url: Option<String>,
+    /// Path of the repository with its namespace, such as `owner/repo`.
+    pub path: String,
+    pub reference: Ref,
+    /// Commit the event refers to, it's checked out with a detached head
+    /// instead of the head of the reference.
+    pub sha: Option<String>,
+}
+
+impl Source {
+    /// URL to clone from with the protocol, the clone URL is used when the
+    /// event has no SSH URL.
+    pub fn url(&self, protocol: Protocol) -> &str {
+        match (protocol, &self.ssh_url) {
+            (Protocol::Ssh, Some(ssh_url)) => ssh_url,
+            _ => &self.url,
+        }
+    }
+}
+
+/// Namespaced path of the repository at a clone URL, such as `owner/repo` for
+/// `https://github.com/owner/repo.git` or `git@github.com:owner/repo.git`.
+pub fn repository_path(url: &str) -> Option<String> {
+    let path = match url.split_once("://") {
+        Some((_, rest)) => rest.split_once('/')?.1,
+        None => url.split_once(':')?.1,
+    };
+    let path = path.trim_matches('/');
+    let path = path.strip_suffix(".git").unwrap_or(path);
+    match path.is_empty() {
+        true => None,
+        false => Some(path.to_string()),
+    }
+}
+
+/// Whether two clone URLs name the same repository, a trailing `.git` or
+/// slash is ignored.
+fn same_repository(a: &str, b: &str) -> bool {
+    fn trim(url: &str) -> &str {
+        url.trim_end_matches('/').trim_end_matches(".git")
+    }
+    trim(a) == trim(b)
+}
+
+/// Checkout of a source.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Checkout {
+    pub path: PathBuf,
+    /// Commit that was checked out.
+    pub sha: String,
+}
+
+/// Repository checkouts kept under a root directory, each one in the
+/// directory of its namespaced path. With a configured repository only that
+/// one is checked out, from its own URL.
+#[derive(Clone, Debug)]
+pub struct Checkouts {
+    root: PathBuf,
+    protocol: Protocol,
+    credentials: Credentials,
+    repository: Option<String>,
+}
+
+impl Checkouts {
+    pub fn new(
+        root: PathBuf,
+        protocol: Protocol,
+        credentials: Credentials,
+        repository: Option<String>,
+    ) -> Self {
+        Checkouts {
+            root,
+            protocol,
+            credentials,
+            repository,
+        }
+    }
+
+    /// Directory of the checkout of the repository at `path`, paths with
+    /// empty, `.` or `..` components are rejected.
+    pub fn path(&self, path: &str) -> Result<PathBuf, GitError> {
+        let mut dir = self.root.clone();
+        for component in path.trim_matches('/').split('/') {
+            if component.is_empty() || component == "." || component == ".." {
+                return Err(GitError::Path(path.to_string()));
+            }
+            dir.push(component);
+        }
+        Ok(dir)
+    }
+
+    /// Clone or update the checkout of the source and check out its commit
+    /// with a detached head. Without a commit the branch is fast-forwarded, or
+    /// the tag is checked out. Sources of another repository than the
+    /// configured one are refused, so credentials are never sent elsewhere.
+    pub fn checkout(&self, source: &Source) -> Result<Checkout, GitError> {
+        let (url, path) = match &self.repository {
+            Some(repository) => {
+                let urls = std::iter::once(&source.url).chain(&source.ssh_url);
+                if !urls.into_iter().any(|url| same_repository(url, repository)) {
+                    return Err(GitError::Repository(source.url.clone()));
+                }
+                let path = repository_path(repository)
+                    .ok_or_else(|| GitError::Path(repository.clone()))?;
+                (repository.as_str(), path)
+            }
+            None => (source.url(self.protocol), source.path.clone()),
+        };
+        let path = self.path(&path)?;
+        let credentials = &self.credentials;
+
+        match path.is_dir() {
+            true => {
+                reset(&path)?;
+                set_url(&path, url)?;
+            }
+            false => clone(url, &path, credentials)?,
+        }
+        match (&source.reference, &source.sha) {
+            // the branch is only fetched for the commit to be known
+            (Ref::Branch(branch), Some(_)) => fetch_branch(&path, branch, credentials)?,
+            (Ref::Branch(branch), None) => fast_forward(&path, branch, credentials)?,
+            (Ref::Tag(tag), _) => checkout_tag(&path, tag, credentials)?,
+            (Ref::Commit, _) => fetch_branches(&path, credentials)?,
+        }
+        if let Some(sha) = &source.sha {
+            checkout_commit(&path, sha)?;
+        }
+
+        let sha = head(&path)?;
+        Ok(Checkout { path, sha })
+    }
+}
+
+fn open(path: &Path) -> Result<git2::Repository, GitError> {
+    git2::Repository::open(path).map_err(GitError::Open)
+}
+
+fn fetch(
+    repo: &git2::Repository,
+    refspec: &str,
+    reference: &str,
+    credentials: &Credentials,
+) -> Result<(), GitError> {
+    let rejected = Cell::new(None);
+    let mut options = credentials.fetch_options(&rejected);
+    repo.find_remote("origin")
+        .and_then(|mut remote| remote.fetch(&[refspec], Some(&mut options), None))
+        .map_err(|error| match rejected.take() {
+            Some(host) => GitError::HostKey(host),
+            None => GitError::Fetch {
+                reference: reference.to_string(),
+                error,
+            },
+        })
+}
+
+/// Clone the repository at `url` into `path`, the parent directories are
+/// created when they don't exist.
+pub fn clone(url: &str, path: &Path, credentials: &Credentials) -> Result<(), GitError> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let rejected = Cell::new(None);
+    let cloned = git2::build::RepoBuilder::new()
+        .fetch_options(credentials.fetch_options(&rejected))
+        .clone(url, path);
+    cloned.map(|_| ()).map_err(|error| match rejected.take() {
+        Some(host) => GitError::HostKey(host),
+        None => GitError::Clone(error),
+    })
+}
+
+/// Name of the branch the HEAD of the repository at `url` points to.
+pub fn default_branch(url: &str, credentials: &Credentials) -> Result<String, GitError> {
+    let rejected = Cell::new(None);
+    let callbacks = match credentials {
+        Credentials::None => git2::RemoteCallbacks::new(),
+        credentials => credentials.callbacks(&rejected),
+    };
+    let mut remote = git2::Remote::create_detached(url).map_err(GitError::DefaultBranch)?;
+    let branch = remote
+        .connect_auth(git2::Direction::Fetch, Some(callbacks), None)
+        .and_then(|connection| connection.default_branch())
+        .map(|name| name.as_str().unwrap_or_default().to_string());
+    let branch = branch.map_err(|error| match rejected.take() {
+        Some(host) => GitError::HostKey(host),
+        None => GitError::DefaultBranch(error),
+    })?;
+    match branch.strip_prefix("refs/heads/") {
+        Some(branch) => Ok(branch.to_string()),
+        None => Err(GitError::DefaultBranch(git2::Error::from_str(&format!(
+            "HEAD points to {}",
+            branch
+        )))),
+    }
+}
+
+/// Point the origin of the checkout at `path` to `url`, so a changed URL or
+/// protocol is used for the next fetch.
+pub fn set_url(path: &Path, url: &str) -> Result<(), GitError> {
+    let repo = open(path)?;
+    repo.remote_set_url("origin", url).map_err(GitError::Remote)
+}
+
+/// Discard local changes of the checkout at `path`.
+pub fn reset(path: &Path) -> Result<(), GitError> {
+    let repo = open(path)?;
+    let head = repo.revparse_single("HEAD").map_err(GitError::Reset)?;
+    repo.reset(&head, git2::ResetType::Hard, None)
+        .map_err(GitError::Reset)
+}
+
+/// Fetch `branch` into its remote-tracking branch without changing the
+/// checkout at `path`.
+pub fn fetch_branch(path: &Path, branch: &str, credentials: &Credentials) -> Result<(), GitError> {
+    let repo = open(path)?;
+    let refspec = format!("+refs/heads/{}:refs/remotes/origin/{}", branch, branch);
+    fetch(&repo, &refspec, branch, credentials)
+}
+
+/// Fetch every branch into its remote-tracking branch without changing the
+/// checkout at `path`.
+pub fn fetch_branches(path: &Path, credentials: &Credentials) -> Result<(), GitError> {
+    let repo = open(path)?;
+    let refspec = "+refs/heads/*:refs/remotes/origin/*";
+    fetch(&repo, refspec, "refs/heads/*", credentials)
+}
+
+/// Fetch `branch`, fast-forward the local branch of the checkout at `path` to
+/// it and check it out. The local branch is created when it doesn't exist.
+pub fn fast_forward(path: &Path, branch: &str, credentials: &Credentials) -> Result<(), GitError> {
+    let repo = open(path)?;
+    fetch(&repo, branch, branch, credentials)?;
+
+    let checkout = |error| GitError::Checkout {
+        reference: branch.to_string(),
+        error,
+    };
+    let fetched = repo
+        .revparse_single("FETCH_HEAD")
+        .and_then(|object| object.peel_to_commit())
+        .map_err(checkout)?;
+    let refname = format!("refs/heads/{}", branch);
+
+    match repo.find_reference(&refname) {
+        Ok(mut reference) => {
+            let local = reference.peel_to_commit().map_err(checkout)?;
+            if local.id() != fetched.id() {
+                let descendant = repo
+                    .graph_descendant_of(fetched.id(), local.id())
+                    .map_err(checkout)?;
+                if !descendant {
+                    return Err(GitError::NotFastForward {
+                        branch: branch.to_string(),
+                    });
+                }
+                reference
+                    .set_target(fetched.id(), "Fast-Forward")
+                    .map_err(checkout)?;
+            }
+        }
+        Err(_) => {
+            repo.branch(branch, &fetched, false).map_err(checkout)?;
+        }
+    }
+
+    repo.set_head(&refname).map_err(checkout)?;
+    repo.checkout_head(Some(git2::build::CheckoutBuilder::default().force()))
+        .map_err(checkout)
+}
+
+/// Check out `reference` with a detached head.
+fn checkout_detached(repo: &git2::Repository, reference: &str) -> Result<(), git2::Error> {
+    let commit = repo.revparse_single(reference)?.peel_to_commit()?;
+
+    repo.checkout_tree(
+        commit.as_object(),
+        Some(git2::build::CheckoutBuilder::default().force()),
+    )?;
+    repo.set_head_detached(commit.id())
+}
+
+/// Fetch the tag and check out the tagged commit with a detached head.
+pub fn checkout_tag(path: &Path, tag: &str, credentials: &Credentials) -> Result<(), GitError> {
+    let repo = open(path)?;
+    let refname = format!("refs/tags/{}", tag);
+    let refspec = format!("+{}:{}", refname, refname);
+
+    fetch(&repo, &refspec, &refname, credentials)?;
+    checkout_detached(&repo, &refname).map_err(|error| GitError::Checkout {
+        reference: refname,
+        error,
+    })
+}
+
+/// Check out a commit of the repository at `path` with a detached head.
+pub fn checkout_commit(path: &Path, sha: &str) -> Result<(), GitError> {
+    let repo = open(path)?;
+    checkout_detached(&repo, sha).map_err(|error| GitError::Checkout {
+        reference: sha.to_string(),
+        error,
+    })
+}
+
+/// Commit the head of the checkout at `path` points to.
+pub fn head(path: &Path) -> Result<String, GitError> {
+    let repo = open(path)?;
+    repo.revparse_single("HEAD")
+        .and_then(|object| object.peel_to_commit())
+        .map(|commit| commit.id().to_string())
+        .map_err(|error| GitError::Checkout {
+            reference: "HEAD".to_string(),
+            error,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+
+    fn git(dir: &Path, args: &[&str]) -> String {
+        let output = Command::new("git")
+            .args(args)
+            .current_dir(dir)
+            .env("GIT_AUTHOR_NAME", "shook")
+            .env("GIT_AUTHOR_EMAIL", "shook@example.com")
+            .env("GIT_COMMITTER_NAME", "shook")
+            .env("GIT_COMMITTER_EMAIL", "shook@example.com")
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap().trim().to_string()
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("shook-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn it_reports_git_errors() {
+        let dir = temp_dir("git");
+        let origin = dir.join("origin");
+        fs::create_dir_all(&origin).unwrap();
+        git(&origin, &["init", "-q", "-b", "main"]);
+        git(&origin, &["commit", "-q", "--allow-empty", "-m", "first"]);
+
+        let none = Credentials::None;
+        let missing = clone(
+            dir.join("missing").to_str().unwrap(),
+            &dir.join("none"),
+            &none,
+        );
+        assert!(matches!(missing, Err(GitError::Clone(_))));
+        assert!(matches!(reset(&dir.join("none")), Err(GitError::Open(_))));
+
+        let checkout = dir.join("cache").join("checkout");
+        clone(origin.to_str().unwrap(), &checkout, &none).unwrap();
+        reset(&checkout).unwrap();
+        fast_forward(&checkout, "main", &none).unwrap();
+
+        let error = checkout_commit(&checkout, "0000000").unwrap_err();
+        assert!(matches!(error, GitError::Checkout { .. }));
+        assert!(error
+            .to_string()
+            .starts_with("failed to check out 0000000: "));
+        let error = checkout_tag(&checkout, "v1.0.0", &none).unwrap_err();
+        assert!(error.to_string().contains("refs/tags/v1.0.0"));
+
+        git(&checkout, &["commit", "-q", "--allow-empty", "-m", "local"]);
+        git(&origin, &["commit", "-q", "--allow-empty", "-m", "second"]);
+        assert!(matches!(
+            fast_forward(&checkout, "main", &none),
+            Err(GitError::NotFastForward { .. })
+        ));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn it_keys_checkouts_by_namespaced_path() {
+        let checkouts = Checkouts::new(
+            PathBuf::from("/var/cache/shook"),
+            Protocol::Https,
+            Credentials::None,
+            None,
+        );
+
+        assert_eq!(
+            checkouts.path("org-a/api").unwrap(),
+            PathBuf::from("/var/cache/shook/org-a/api")
+        );
+        assert_eq!(
+            checkouts.path("group/sub/api").unwrap(),
+            PathBuf::from("/var/cache/shook/group/sub/api")
+        );
+        assert_ne!(
+            checkouts.path("org-a/api").unwrap(),
+            checkouts.path("org-b/api").unwrap()
+        );
+        for path in &["", "../api", "org/./api", "org//api", "org/.."] {
+            assert!(matches!(checkouts.path(path), Err(GitError::Path(_))));
+        }
+    }
+
+    #[test]
+    fn it_selects_clone_urls() {
+        let mut source = Source {
+            url: "https://github.com/org/api.git".to_string(),
+            ssh_url: Some("git@github.com:org/api.git".to_string()),
+            path: "org/api".to_string(),
+            reference: Ref::Branch("main".to_string()),
+            sha: None,
+        };
+        assert_eq!(
+            source.url(Protocol::Https),
+            "https://github.com/org/api.git"
+        );
+        assert_eq!(source.url(Protocol::Ssh), "git@github.com:org/api.git");
+
+        source.ssh_url = None;
+        assert_eq!(source.url(Protocol::Ssh), "https://github.com/org/api.git");
+    }
+
+    #[test]
+    fn it_reads_repository_paths() {
+        let paths = [
+            (
+                "https://gitlab.com/group/sub/api.git",
+                Some("group/sub/api"),
+            ),
+            ("git@github.com:org/api.git", Some("org/api")),
+            ("ssh://git@example.com:2222/org/api/", Some("org/api")),
+            ("https://example.com/", None),
+            ("/srv/git/api.git", None),
+        ];
+        for (url, path) in paths.iter() {
+            assert_eq!(repository_path(url).as_deref(), *path, "{}", url);
+        }
+        assert_eq!(Protocol::of("https://github.com/org/api"), Protocol::Https);
+        assert_eq!(Protocol::of("git@github.com:org/api.git"), Protocol::Ssh);
+    }
+
+    #[test]
+    fn it_only_checks_out_the_configured_repository() {
+        let dir = temp_dir("pinned");
+        let origin = dir.join("origin");
+        fs::create_dir_all(&origin).unwrap();
+        git(&origin, &["init", "-q", "-b", "main"]);
+        git(&origin, &["commit", "-q", "--allow-empty", "-m", "first"]);
+
+        let url = format!("file://{}", origin.display());
+        let checkouts = Checkouts::new(
+            dir.join("cache"),
+            Protocol::Https,
+            Credentials::None,
+            Some(url.clone()),
+        );
+        let source = |url: &str, path: &str| Source {
+            url: url.to_string(),
+            ssh_url: None,
+            path: path.to_string(),
+            reference: Ref::Branch("main".to_string()),
+            sha: None,
+        };
+
+        let error = checkouts
+            .checkout(&source("https://attacker.example/x.git", "org/api"))
+            .unwrap_err();
+        assert!(matches!(error, GitError::Repository(_)));
+        assert!(!dir.join("cache").exists());
+
+        // the checkout is kept at the path of the configured repository
+        let named = format!("{}.git", url);
+        let checkout = checkouts.checkout(&source(&named, "other/api")).unwrap();
+        let path = repository_path(&url).unwrap();
+        assert_eq!(checkout.path, dir.join("cache").join(path));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn it_checks_known_hosts() {
+        let dir = temp_dir("known-hosts");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("known_hosts");
+        let hash = |key: &[u8]| -> [u8; 32] { Sha256::digest(key).into() };
+        let hosts = format!(
+            "# comment\n\
+             gitlab.com,172.65.251.78 ssh-ed25519 {}\n\
+             [git.example.com]:2222 ssh-rsa {}\n\
+             |1|c2FsdA==|aGFzaA== ssh-ed25519 {}\n",
+            base64::encode(b"gitlab"),
+            base64::encode(b"example"),
+            base64::encode(b"hashed"),
+        );
+        fs::write(&path, hosts).unwrap();
+
+        assert!(known_host(&path, "gitlab.com", &hash(b"gitlab")));
+        assert!(known_host(&path, "172.65.251.78", &hash(b"gitlab")));
+        assert!(known_host(&path, "git.example.com", &hash(b"example")));
+        assert!(!known_host(&path, "gitlab.com", &hash(b"example")));
+        assert!(!known_host(&path, "github.com", &hash(b"gitlab")));
+        assert!(!known_host(&path, "c2FsdA==", &hash(b"hashed")));
+        assert!(!known_host(
+            &dir.join("missing"),
+            "gitlab.com",
+            &hash(b"gitlab")
+        ));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn it_hides_credentials() {
+        let key = Credentials::SshKey {
+            path: PathBuf::from("/etc/shook/id_ed25519"),
+            passphrase: Some("hunter2".to_string()),
+            known_hosts: PathBuf::from("/etc/shook/known_hosts"),
+        };
+        let token = Credentials::Token {
+            username: "oauth2".to_string(),
+            token: "glpat-123".to_string(),
+        };
+        assert_eq!(format!("{:?}", key), "SshKey(/etc/shook/id_ed25519)");
+        assert_eq!(format!("{:?}", token), "Token(oauth2)");
+    }
+
+    #[test]
+    fn it_checks_out_sources() {
+        let dir = temp_dir("checkouts");
+        let origin = dir.join("origin");
+        fs::create_dir_all(&origin).unwrap();
+        git(&origin, &["init", "-q", "-b", "main"]);
+        git(&origin, &["commit", "-q", "--allow-empty", "-m", "first"]);
+        let first = git(&origin, &["rev-parse", "HEAD"]);
+        git(&origin, &["tag", "v1.0.0"]);
+        git(&origin, &["checkout", "-q", "-b", "staging"]);
+        git(&origin, &["commit", "-q", "--allow-empty", "-m", "staging"]);
+        let staging = git(&origin, &["rev-parse", "HEAD"]);
+        git(&origin, &["checkout", "-q", "main"]);
+
+        let checkouts = Checkouts::new(dir.join("cache"), Protocol::Https, Credentials::None, None);
+        let source = |reference, sha: Option<&str>| Source {
+            url: origin.to_str().unwrap().to_string(),
+            ssh_url: None,
+            path: "org/api".to_string(),
+            reference,
+            sha: sha.map(str::to_string),
+        };
+        let head = |path: &Path| git(path, &["rev-parse", "HEAD"]);
+
+        let branch = Ref::Branch("staging".to_string());
+        let checkout = checkouts.checkout(&source(branch, None)).unwrap();
+        let path = checkout.path;
+        assert_eq!(path, dir.join("cache/org/api"));
+        assert_eq!(checkout.sha, staging);
+        assert_eq!(head(&path), staging);
+
+        let tag = Ref::Tag("v1.0.0".to_string());
+        assert_eq!(checkouts.checkout(&source(tag, None)).unwrap().sha, first);
+
+        git(&origin, &["commit", "-q", "--allow-empty", "-m", "second"]);
+        let second = git(&origin, &["rev-parse", "HEAD"]);
+        git(&origin, &["commit", "-q", "--allow-empty", "-m", "third"]);
+        let main = Ref::Branch("main".to_string());
+        checkouts.checkout(&source(main.clone(), None)).unwrap();
+        assert_eq!(head(&path), head(&origin));
+
+        // the commit of the event is checked out, not the newest one
+        let checkout = checkouts
+            .checkout(&source(main, Some(second.as_str())))
+            .unwrap();
+        assert_eq!(checkout.sha, second);
+        assert_eq!(head(&path), second);
+        assert_eq!(git(&path, &["rev-parse", "--abbrev-ref", "HEAD"]), "HEAD");
+
+        // a commit without a reference is found in any branch
+        git(&origin, &["checkout", "-q", "staging"]);
+        git(&origin, &["commit", "-q", "--allow-empty", "-m", "fix"]);
+        let fix = git(&origin, &["rev-parse", "HEAD"]);
+        git(&origin, &["checkout", "-q", "main"]);
+        let checkout = checkouts.checkout(&source(Ref::Commit, Some(fix.as_str())));
+        assert_eq!(checkout.unwrap().sha, fix);
+
+        let url = origin.to_str().unwrap();
+        assert_eq!(default_branch(url, &Credentials::None).unwrap(), "main");
+
+        // the checkout is fetched from the URL of the source when it moved
+        let moved = dir.join("moved");
+        git(&dir, &["clone", "-q", origin.to_str().unwrap(), "moved"]);
+        git(&moved, &["commit", "-q", "--allow-empty", "-m", "moved"]);
+        let source = Source {
+            url: moved.to_str().unwrap().to_string(),
+            ..source(Ref::Branch("main".to_string()), None)
+        };
+        assert_eq!(checkouts.checkout(&source).unwrap().sha, head(&moved));
+        assert_eq!(git(&path, &["remote", "get-url", "origin"]), source.url);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
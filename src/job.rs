@@ -0,0 +1,376 @@
+use chrono::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use crate::runner::{Cancel, Stream};
+
+/// Single line of command output.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Line {
+    pub stream: Stream,
+    pub text: String,
+}
+
+/// Record of one command of a job.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Step {
+    pub command: String,
+    /// Exit code, `None` while the command runs or when it was killed by a
+    /// signal.
+    pub exit_code: Option<i32>,
+    pub output: Vec<Line>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Queued,
+    Running,
+    Succeeded,
+    Failed,
+    TimedOut,
+    Cancelled,
+}
+
+/// Record of a deployment and the output of its commands.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Job {
+    /// Identifier in the job history, `None` until the job is stored.
+    pub id: Option<i64>,
+    pub project: String,
+    pub provider: String,
+    /// Name of the event that triggered the deployment.
+    pub event: String,
+    /// Commit the event refers to.
+    pub sha: String,
+    /// Source of the deployment, `webhook` or `manual`.
+    pub trigger: String,
+    pub status: Status,
+    /// Index of the step that failed or timed out the job.
+    pub failed_step: Option<usize>,
+    pub queued_at: DateTime<Utc>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+    /// Steps are left out of job listings.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub steps: Vec<Step>,
+}
+
+/// Job record shared between the runner and whoever reports on it.
+pub type JobHandle = Arc<Mutex<Job>>;
+
+/// Entry of the output log of a job.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum LogEvent {
+    /// A command started.
+    Step { step: usize, command: String },
+    /// A command printed a line.
+    Line {
+        step: usize,
+        stream: Stream,
+        text: String,
+    },
+}
+
+impl LogEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            LogEvent::Step { .. } => "step",
+            LogEvent::Line { .. } => "line",
+        }
+    }
+}
+
+/// Position in the output log of a job, used to read the entries that were
+/// added since the last read.
+#[derive(Debug, Default)]
+pub struct Cursor {
+    /// Number of steps that were read.
+    steps: usize,
+    /// Number of lines that were read of the last step.
+    lines: usize,
+}
+
+/// Jobs that are queued or running with their cancellation signal, by id.
+#[derive(Clone, Default)]
+pub struct Registry {
+    jobs: Arc<Mutex<HashMap<i64, (JobHandle, Cancel)>>>,
+}
+
+impl Registry {
+    pub fn insert(&self, id: i64, job: JobHandle, cancel: Cancel) {
+        self.jobs.lock().unwrap().insert(id, (job, cancel));
+    }
+
+    pub fn get(&self, id: i64) -> Option<JobHandle> {
+        self.jobs
+            .lock()
+            .unwrap()
+            .get(&id)
+            .map(|(job, _)| job.clone())
+    }
+
+    pub fn remove(&self, id: i64) -> Option<JobHandle> {
+        self.jobs.lock().unwrap().remove(&id).map(|(job, _)| job)
+    }
+
+    /// Cancel the job, `None` is returned when it isn't queued or running.
+    pub fn cancel(&self, id: i64) -> Option<JobHandle> {
+        let jobs = self.jobs.lock().unwrap();
+        let (job, cancel) = jobs.get(&id)?;
+        cancel.cancel();
+        Some(job.clone())
+    }
+}
+
+impl Job {
+    pub fn new(project: &str) -> Self {
+        Job {
+            id: None,
+            project: project.to_string(),
+            provider: "undefined".to_string(),
+            event: "undefined".to_string(),
+            sha: "undefined".to_string(),
+            trigger: "undefined".to_string(),
+            status: Status::Queued,
+            failed_step: None,
+            queued_at: Utc::now(),
+            started_at: None,
+            finished_at: None,
+            steps: Vec::new(),
+        }
+    }
+
+    pub fn handle(self) -> JobHandle {
+        Arc::new(Mutex::new(self))
+    }
+
+    pub fn start(&mut self) {
+        self.status = Status::Running;
+        self.started_at = Some(Utc::now());
+    }
+
+    pub fn start_step(&mut self, command: &str) {
+        self.steps.push(Step {
+            command: command.to_string(),
+            exit_code: None,
+            output: Vec::new(),
+        });
+    }
+
+    /// Add a line of output to the current step.
+    pub fn push_line(&mut self, stream: Stream, text: String) {
+        if let Some(step) = self.steps.last_mut() {
+            step.output.push(Line { stream, text });
+        }
+    }
+
+    pub fn finish_step(&mut self, exit_code: Option<i32>) {
+        if let Some(step) = self.steps.last_mut() {
+            step.exit_code = exit_code;
+        }
+    }
+
+    pub fn succeed(&mut self) {
+        self.finish(Status::Succeeded, None);
+    }
+
+    /// Mark the job as failed at the step with the given index.
+    pub fn fail(&mut self, step: usize) {
+        self.finish(Status::Failed, Some(step));
+    }
+
+    /// Mark the job as failed before its commands ran, the error is recorded
+    /// as the output of a `checkout` step.
+    pub fn fail_checkout(&mut self, error: String) {
+        self.start_step("checkout");
+        self.push_line(Stream::Stderr, error);
+        self.finish_step(None);
+        self.fail(self.steps.len() - 1);
+    }
+
+    /// Mark the job as timed out at the step with the given index.
+    pub fn time_out(&mut self, step: usize) {
+        self.finish(Status::TimedOut, Some(step));
+    }
+
+    /// Mark the job as cancelled, at the step with the given index when one
+    /// was running.
+    pub fn cancel(&mut self, step: Option<usize>) {
+        self.finish(Status::Cancelled, step);
+    }
+
+    /// Whether the job has ended, successfully or not.
+    pub fn is_finished(&self) -> bool {
+        !matches!(self.status, Status::Queued | Status::Running)
+    }
+
+    /// Output log entries added since the position of the cursor, the cursor
+    /// is moved past them.
+    pub fn log_since(&self, cursor: &mut Cursor) -> Vec<LogEvent> {
+        let mut events = Vec::new();
+        let skip = cursor.steps.saturating_sub(1);
+        for (index, step) in self.steps.iter().enumerate().skip(skip) {
+            if index >= cursor.steps {
+                events.push(LogEvent::Step {
+                    step: index,
+                    command: step.command.clone(),
+                });
+                cursor.steps = index + 1;
+                cursor.lines = 0;
+            }
+            for line in step.output.iter().skip(cursor.lines) {
+                events.push(LogEvent::Line {
+                    step: index,
+                    stream: line.stream,
+                    text: line.text.clone(),
+                });
+            }
+            cursor.lines = step.output.len();
+        }
+        events
+    }
+
+    fn finish(&mut self, status: Status, failed_step: Option<usize>) {
+        self.status = status;
+        self.failed_step = failed_step;
+        self.finished_at = Some(Utc::now());
+    }
+
+    pub fn dump(&self) {
+        let log = slog_scope::logger();
+
+        debug!(log, "job";
+            "id" => format!("{:?}", self.id),
+            "project" => &self.project,
+            "event" => &self.event,
+            "sha" => &self.sha,
+            "status" => format!("{:?}", self.status),
+            "failed_step" => format!("{:?}", self.failed_step),
+        );
+        for step in self.steps.iter() {
+            debug!(log, "job step";
+                "project" => &self.project,
+                "command" => &step.command,
+                "exit_code" => format!("{:?}", step.exit_code),
+                "lines" => step.output.len(),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_records_steps() {
+        let mut job = Job::new("sample");
+        assert_eq!(job.status, Status::Queued);
+        job.start();
+        job.push_line(Stream::Stdout, "dropped".to_string());
+        job.start_step("echo one");
+        job.push_line(Stream::Stdout, "one".to_string());
+        job.finish_step(Some(0));
+        job.start_step("false");
+        job.push_line(Stream::Stderr, "failed".to_string());
+        job.finish_step(Some(1));
+
+        assert_eq!(job.steps.len(), 2);
+        assert_eq!(job.steps[0].command, "echo one".to_string());
+        assert_eq!(job.steps[0].exit_code, Some(0));
+        assert_eq!(
+            job.steps[0].output,
+            vec![Line {
+                stream: Stream::Stdout,
+                text: "one".to_string()
+            }]
+        );
+        assert_eq!(job.steps[1].exit_code, Some(1));
+        assert_eq!(job.steps[1].output[0].stream, Stream::Stderr);
+        assert_eq!(job.status, Status::Running);
+        assert_eq!(job.finished_at, None);
+
+        job.fail(1);
+        assert_eq!(job.status, Status::Failed);
+        assert_eq!(job.failed_step, Some(1));
+        assert!(job.finished_at.is_some());
+    }
+
+    #[test]
+    fn it_reads_log_since_cursor() {
+        let mut job = Job::new("sample");
+        let mut cursor = Cursor::default();
+        assert!(job.log_since(&mut cursor).is_empty());
+
+        job.start_step("echo one");
+        job.push_line(Stream::Stdout, "one".to_string());
+        assert_eq!(
+            job.log_since(&mut cursor),
+            vec![
+                LogEvent::Step {
+                    step: 0,
+                    command: "echo one".to_string()
+                },
+                LogEvent::Line {
+                    step: 0,
+                    stream: Stream::Stdout,
+                    text: "one".to_string()
+                },
+            ]
+        );
+        assert!(job.log_since(&mut cursor).is_empty());
+
+        job.push_line(Stream::Stdout, "two".to_string());
+        job.finish_step(Some(0));
+        job.start_step("echo three");
+        job.push_line(Stream::Stderr, "three".to_string());
+        let events = job.log_since(&mut cursor);
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0].name(), "line");
+        assert_eq!(
+            events[1],
+            LogEvent::Step {
+                step: 1,
+                command: "echo three".to_string()
+            }
+        );
+        assert_eq!(
+            events[2],
+            LogEvent::Line {
+                step: 1,
+                stream: Stream::Stderr,
+                text: "three".to_string()
+            }
+        );
+
+        // a new cursor replays the whole log
+        assert_eq!(job.log_since(&mut Cursor::default()).len(), 5);
+    }
+
+    #[test]
+    fn it_tracks_finished_jobs() {
+        let mut job = Job::new("sample");
+        assert!(!job.is_finished());
+        job.start();
+        assert!(!job.is_finished());
+        job.cancel(None);
+        assert!(job.is_finished());
+        assert_eq!(job.status, Status::Cancelled);
+    }
+
+    #[test]
+    fn it_fails_checkouts() {
+        let mut job = Job::new("sample");
+        job.start();
+        job.fail_checkout("failed to clone repository".to_string());
+
+        assert_eq!(job.status, Status::Failed);
+        assert_eq!(job.failed_step, Some(0));
+        assert_eq!(job.steps[0].command, "checkout");
+        assert_eq!(job.steps[0].output[0].stream, Stream::Stderr);
+        assert_eq!(job.steps[0].output[0].text, "failed to clone repository");
+    }
+}
@@ -0,0 +1,103 @@
+use std::collections::HashMap;
+use yaml_rust::parser::{Event, Parser};
+
+/// Container that's being read, with the entry the next node belongs to.
+enum Frame {
+    /// `key` is `None` while the next node is a key.
+    Mapping {
+        key: Option<String>,
+    },
+    Sequence {
+        index: usize,
+    },
+}
+
+fn join(path: &str, key: &str) -> String {
+    match path.is_empty() {
+        true => key.to_string(),
+        false => format!("{}.{}", path, key),
+    }
+}
+
+/// Path of the value node that comes next.
+fn value_path(frames: &[(String, Frame)]) -> String {
+    match frames.last() {
+        None => String::new(),
+        Some((path, Frame::Mapping { key: Some(key) })) => join(path, key),
+        Some((path, Frame::Mapping { key: None })) => path.clone(),
+        Some((path, Frame::Sequence { index })) => format!("{}[{}]", path, index),
+    }
+}
+
+/// Move the innermost container past the value that was just read.
+fn value_read(frames: &mut [(String, Frame)]) {
+    match frames.last_mut() {
+        Some((_, Frame::Mapping { key })) => *key = None,
+        Some((_, Frame::Sequence { index })) => *index += 1,
+        None => {}
+    }
+}
+
+/// Line and column of every node of a YAML document by its path, such as
+/// `projects[1].token`. Mapping entries are located at their key. Reading
+/// stops at the first syntax error.
+pub fn positions(input: &str) -> HashMap<String, (usize, usize)> {
+    let mut parser = Parser::new(input.chars());
+    let mut frames: Vec<(String, Frame)> = Vec::new();
+    let mut positions = HashMap::new();
+
+    while let Ok((event, marker)) = parser.next() {
+        let position = (marker.line(), marker.col() + 1);
+        match event {
+            Event::StreamEnd => break,
+            Event::Scalar(value, ..) => {
+                if let Some((path, Frame::Mapping { key })) = frames.last_mut() {
+                    if key.is_none() {
+                        positions.entry(join(path, &value)).or_insert(position);
+                        *key = Some(value);
+                        continue;
+                    }
+                }
+                positions.entry(value_path(&frames)).or_insert(position);
+                value_read(&mut frames);
+            }
+            Event::Alias(_) => {
+                positions.entry(value_path(&frames)).or_insert(position);
+                value_read(&mut frames);
+            }
+            Event::MappingStart(_) | Event::SequenceStart(_) => {
+                let path = value_path(&frames);
+                positions.entry(path.clone()).or_insert(position);
+                let frame = match event {
+                    Event::MappingStart(_) => Frame::Mapping { key: None },
+                    _ => Frame::Sequence { index: 0 },
+                };
+                frames.push((path, frame));
+            }
+            Event::MappingEnd | Event::SequenceEnd => {
+                frames.pop();
+                value_read(&mut frames);
+            }
+            _ => {}
+        }
+    }
+    positions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_locates_nodes() {
+        let input = "queue:\n  concurrency: 2\nprojects:\n  - name: one\n    commands: [a, b]\n  - name: two\n    token: ''\n";
+        let positions = positions(input);
+
+        assert_eq!(positions.get("queue"), Some(&(1, 1)));
+        assert_eq!(positions.get("queue.concurrency"), Some(&(2, 3)));
+        assert_eq!(positions.get("projects[0].name"), Some(&(4, 5)));
+        assert_eq!(positions.get("projects[0].commands[1]"), Some(&(5, 19)));
+        assert_eq!(positions.get("projects[1].name"), Some(&(6, 5)));
+        assert_eq!(positions.get("projects[1].token"), Some(&(7, 5)));
+    }
+}
@@ -4,70 +4,345 @@ extern crate slog_async;
 extern crate slog_json;
 extern crate slog_scope;
 
+mod api;
 mod cmd;
 mod config;
+mod git;
+mod job;
+mod locate;
+mod queue;
+mod reload;
+mod rules;
+mod runner;
+mod secret;
+mod store;
 mod webhook;
 
 use actix_slog::StructuredLogger;
 use actix_web::{
-    error, get, http::header::HeaderMap, post, web, App, Error, HttpRequest, HttpResponse,
-    HttpServer,
+    get, http::header::HeaderMap, post, web, App, Error, HttpRequest, HttpResponse, HttpServer,
 };
-use async_std::task;
 use chrono::prelude::*;
 use futures::StreamExt;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use slog::Drain;
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
 
+use api::ApiError;
 use cmd::ShookArgs;
-use config::{Config, Provider};
-use webhook::gitlab::Webhook as GitLabWebhook;
-use webhook::github::{self, Webhook as GitHubWebhook};
+use config::{Config, Project, Provider, ServerSettings, SharedConfig};
+use git::{Checkout, Checkouts, GitError, Ref};
+use job::{Cursor, Job, JobHandle, Registry, Status};
+use queue::Queue;
+use runner::Cancel;
+use secret::Secret;
+use store::Store;
+use webhook::github::{self, Event as GitHubEvent, Push as GitHubPush};
+use webhook::gitlab::{Event as GitLabEvent, Push as GitLabPush};
 
-const MAX_SIZE: usize = 262_144; // max payload size is 256k
+const JOBS_LIMIT: usize = 50; // default number of jobs listed
+const JOBS_LIMIT_MAX: usize = 500; // maximum number of jobs listed
+const WATCH_INTERVAL: Duration = Duration::from_secs(2); // config file check interval
+const LOG_POLL: Duration = Duration::from_millis(250); // log stream update interval
 
+/// Body of a manual deployment request, the repository is the one of the
+/// project.
 #[derive(Deserialize)]
-struct TriggerInfo {
-    path: String,
-    repo: String,
+#[serde(deny_unknown_fields)]
+struct DeployRequest {
+    /// Branch to deploy, the default branch of the repository when neither
+    /// it nor a commit is given.
+    r#ref: Option<String>,
+    /// Commit id to check out instead of the head of the branch.
+    sha: Option<String>,
+}
+
+/// Event built for a manual deployment of a project.
+enum ManualEvent {
+    GitLab(GitLabEvent),
+    GitHub(GitHubEvent),
+}
+
+#[derive(Serialize)]
+struct QueuedJob {
+    id: i64,
+    status: Status,
+    status_url: String,
+}
+
+#[derive(Serialize)]
+struct JobStatus {
+    id: i64,
+    status: Status,
+}
+
+#[derive(Deserialize)]
+struct JobsQuery {
+    project: Option<String>,
+    limit: Option<usize>,
 }
 
 fn verify_gitlab(headers: &HeaderMap, token: &str) -> bool {
     match headers.get("X-Gitlab-Token") {
-        Some(value) => value.to_str().unwrap() == token,
+        Some(value) => match value.to_str() {
+            Ok(value) => value == token,
+            Err(_) => false,
+        },
         None => false,
     }
 }
 
 fn verify_github(headers: &HeaderMap, secret: &str, body: &[u8]) -> bool {
     match headers.get("X-Hub-Signature-256") {
-        Some(value) => {
-            match value.to_str() {
-                Ok(signature) => github::verify_signature(secret, body, signature),
-                Err(_) => false,
+        Some(value) => match value.to_str() {
+            Ok(signature) => github::verify_signature(secret, body, signature),
+            Err(_) => false,
+        },
+        None => false,
+    }
+}
+
+/// Token of the `Authorization: Bearer` header.
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get("Authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+}
+
+/// Whether `sha` is a full or abbreviated hexadecimal commit id.
+fn valid_sha(sha: &str) -> bool {
+    (4..=40).contains(&sha.len()) && sha.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Check the `Authorization: Bearer` header against the admin token, requests
+/// are refused when no admin token is configured.
+fn verify_admin(headers: &HeaderMap, config: &Config) -> Result<(), ApiError> {
+    let token = match &config.admin_token {
+        Some(token) => token.expose(),
+        None => return Err(ApiError::Forbidden("job management is disabled")),
+    };
+    let bearer = bearer_token(headers);
+    match bearer {
+        Some(bearer) if bearer == token => Ok(()),
+        _ => Err(ApiError::Unauthorized("invalid admin token")),
+    }
+}
+
+/// Check the `Authorization: Bearer` header against the project token or the
+/// admin token.
+fn verify_deploy(headers: &HeaderMap, project: &Project, config: &Config) -> Result<(), ApiError> {
+    let bearer = bearer_token(headers);
+    match bearer {
+        Some(bearer) if bearer == project.token.expose() => Ok(()),
+        Some(bearer) if config.admin_token.as_ref().map(Secret::expose) == Some(bearer) => Ok(()),
+        _ => Err(ApiError::Unauthorized("invalid token")),
+    }
+}
+
+/// Check the `Authorization: Bearer` header for access to the jobs of
+/// `project`. The admin token gives access to the jobs of every project, a
+/// project token only to the jobs of its own project.
+fn verify_jobs(
+    headers: &HeaderMap,
+    project: Option<&str>,
+    config: &Config,
+) -> Result<(), ApiError> {
+    if verify_admin(headers, config).is_ok() {
+        return Ok(());
+    }
+    match project.and_then(|name| config.get_project(name.to_string())) {
+        Some(project) => verify_deploy(headers, &project, config),
+        None => Err(ApiError::Unauthorized("invalid token")),
+    }
+}
+
+/// Name of the GitLab event from the `X-Gitlab-Event` header, requests without
+/// the header are treated as merge request events.
+fn gitlab_event(headers: &HeaderMap) -> String {
+    match headers.get("X-Gitlab-Event") {
+        Some(value) => value.to_str().unwrap_or("undefined").to_string(),
+        None => "Merge Request Hook".to_string(),
+    }
+}
+
+/// Name of the GitHub event from the `X-GitHub-Event` header, requests without
+/// the header are treated as pull request events.
+fn github_event(headers: &HeaderMap) -> String {
+    match headers.get("X-GitHub-Event") {
+        Some(value) => value.to_str().unwrap_or("undefined").to_string(),
+        None => "pull_request".to_string(),
+    }
+}
+
+/// Record for a deployment of the project, `source` is what started the
+/// deployment.
+fn job_record(project: &Project, event: &rules::Event, sha: String, source: &str) -> Job {
+    let mut record = Job::new(&project.name);
+    record.provider = project.provider.name().to_string();
+    record.event = event.kind.clone();
+    record.sha = sha;
+    record.trigger = source.to_string();
+    record
+}
+
+/// Repository checkouts in the directory of the project in the cache directory
+/// with its git settings. Projects that deploy the same repository don't share
+/// a checkout, their jobs aren't run one after the other.
+fn checkouts(settings: &ServerSettings, project: &Project) -> Checkouts {
+    Checkouts::new(
+        settings.cache_dir().join(&project.name),
+        project.git.protocol(),
+        project.credentials(),
+        project.git.repository.clone(),
+    )
+}
+
+/// Queue the deployment of the project and return the id of its job. The job
+/// is added to the history as queued, the repository is updated by `checkout`
+/// on a blocking thread when the job starts and the commands are run after it,
+/// the job fails without running them when the checkout fails. The job is kept
+/// in the registry until it has finished.
+#[allow(clippy::too_many_arguments)]
+fn enqueue<F>(
+    queue: &Queue,
+    store: web::Data<Store>,
+    registry: web::Data<Registry>,
+    config: Arc<Config>,
+    project: Project,
+    mut record: Job,
+    mut env: HashMap<String, String>,
+    checkout: F,
+) -> Result<i64, ApiError>
+where
+    F: FnOnce() -> Result<Checkout, GitError> + Send + 'static,
+{
+    let log = slog_scope::logger();
+    let name = project.name.clone();
+    let coalesce = project.coalesce;
+    let id = store.insert(&record)?;
+    record.id = Some(id);
+    let record = record.handle();
+    let cancel = Cancel::default();
+    registry.insert(id, record.clone(), cancel.clone());
+
+    let job_log = log.clone();
+    let job_store = store.clone();
+    let job_registry = registry.clone();
+    let job = async move {
+        {
+            let mut record = record.lock().unwrap();
+            if cancel.is_cancelled() {
+                // cancelled while it was queued
+                if !record.is_finished() {
+                    record.cancel(None);
+                }
+                if let Err(e) = job_store.cancel(id) {
+                    error!(job_log, "failed to store job"; "id" => id, "error" => e.to_string());
+                }
+                job_registry.remove(id);
+                return;
+            }
+            record.start();
+            if let Err(e) = job_store.update(id, &record) {
+                error!(job_log, "failed to store job"; "id" => id, "error" => e.to_string());
             }
         }
-        None => false,
+        // libgit2 blocks, it mustn't hold up the other jobs of the executor
+        match async_std::task::spawn_blocking(checkout).await {
+            Ok(checkout) => {
+                debug!(job_log, "checked out repository";
+                    "path" => checkout.path.display().to_string(),
+                    "sha" => &checkout.sha,
+                );
+                record.lock().unwrap().sha = checkout.sha.clone();
+                env.insert("SHOOK_SHA".to_string(), checkout.sha);
+                let checkout = Some(checkout.path);
+                config
+                    .execute_commands(project, env, checkout, record.clone(), cancel)
+                    .await;
+            }
+            Err(e) => {
+                error!(job_log, "failed to check out repository, skipping commands"; "id" => id, "error" => e.to_string());
+                record.lock().unwrap().fail_checkout(e.to_string());
+            }
+        }
+        let record = record.lock().unwrap();
+        if let Err(e) = job_store.update(id, &record) {
+            error!(job_log, "failed to store job"; "id" => id, "error" => e.to_string());
+        }
+        job_registry.remove(id);
+        record.dump();
+    };
+
+    match queue.submit(&name, id, coalesce, Box::pin(job)) {
+        Ok(dropped) => {
+            debug!(log, "queued deployment"; "project" => &name, "id" => id, "pending" => queue.pending(&name));
+            for dropped in dropped {
+                if let Some(job) = registry.remove(dropped) {
+                    job.lock().unwrap().cancel(None);
+                }
+                if let Err(e) = store.cancel(dropped) {
+                    error!(log, "failed to store job"; "id" => dropped, "error" => e.to_string());
+                }
+            }
+            Ok(id)
+        }
+        Err(e) => {
+            warn!(log, "failed to queue deployment"; "project" => &name, "error" => e.to_string());
+            registry.remove(id);
+            if let Err(e) = store.remove(id) {
+                error!(log, "failed to remove job"; "id" => id, "error" => e.to_string());
+            }
+            Err(ApiError::Unavailable(e.to_string()))
+        }
     }
 }
 
+/// Response for a queued job, `status_url` is where the status of the job can
+/// be polled.
+fn queued(req: &HttpRequest, id: i64) -> HttpResponse {
+    let info = req.connection_info();
+    HttpResponse::Accepted().json(QueuedJob {
+        id,
+        status: Status::Queued,
+        status_url: format!("{}://{}/jobs/{}/status", info.scheme(), info.host(), id),
+    })
+}
+
 #[post("/webhook/{project_name}")]
+#[allow(clippy::too_many_arguments)]
 async fn webhook_handler(
-    data: web::Data<Config>,
+    data: web::Data<SharedConfig>,
+    settings: web::Data<ServerSettings>,
+    queue: web::Data<Queue>,
+    store: web::Data<Store>,
+    registry: web::Data<Registry>,
     req: HttpRequest,
     web::Path(project_name): web::Path<String>,
     mut payload: web::Payload,
-) -> Result<HttpResponse, Error> {
+) -> Result<HttpResponse, ApiError> {
     let log = slog_scope::logger();
-    let project = data.get_project(project_name.clone()).unwrap();
+    let config = data.get();
+    let project = match config.get_project(project_name.clone()) {
+        Some(project) => project,
+        None => {
+            debug!(log, "webhook for unknown project"; "project" => &project_name);
+            return Err(ApiError::ProjectNotFound(project_name));
+        }
+    };
 
     // Read the body first
     let mut body = web::BytesMut::new();
     while let Some(chunk) = payload.next().await {
         let chunk = chunk?;
         // limit max size of in-memory payload
-        if (body.len() + chunk.len()) > MAX_SIZE {
-            return Err(error::ErrorBadRequest("overflow"));
+        if (body.len() + chunk.len()) > settings.payload_limit() {
+            return Err(ApiError::PayloadTooLarge(settings.payload_limit()));
         }
         body.extend_from_slice(&chunk);
     }
@@ -75,167 +350,355 @@ async fn webhook_handler(
     // Handle based on provider type
     match project.provider {
         Provider::GitLab => {
-            if !verify_gitlab(req.headers(), &project.token) {
+            if !verify_gitlab(req.headers(), project.token.expose()) {
                 warn!(log, "X-Gitlab-Token header verification failed");
-                return Ok(HttpResponse::Unauthorized().into());
+                return Err(ApiError::Unauthorized("invalid token"));
             }
 
             debug!(log, "X-Gitlab-Token header verified");
-            let webhook = serde_json::from_slice::<GitLabWebhook>(&body)?;
+            let event_name = gitlab_event(req.headers());
+            let webhook = match GitLabEvent::parse(&event_name, &body)? {
+                Some(webhook) => webhook,
+                None => {
+                    debug!(log, "ignoring unsupported gitlab event"; "event" => &event_name);
+                    return Ok(api::ignored(format!(
+                        "unsupported gitlab event {}",
+                        event_name
+                    )));
+                }
+            };
             webhook.dump();
 
-            if config::should_deploy(
-                webhook.target_branch(),
-                webhook.action(),
-                webhook.merge_status(),
-            ) {
-                match webhook.clone_repository() {
-                    Ok(repo_path) => debug!(log, "cloned repository"; "path" => repo_path),
-                    Err(e) => error!(log, "failed to clone"; "error" => e),
-                }
-                let project_clone = project.clone();
-                task::spawn(async move { data.execute_commands(project_clone).await });
+            let event = webhook.event();
+            if project.should_deploy(&event) {
+                let record = job_record(&project, &event, webhook.sha(), "webhook");
+                let env = webhook.env();
+                let checkouts = checkouts(&settings, &project);
+                let source = webhook.git_source();
+                let id = enqueue(
+                    &queue,
+                    store,
+                    registry,
+                    config,
+                    project,
+                    record,
+                    env,
+                    move || checkouts.checkout(&source),
+                )?;
+                return Ok(queued(&req, id));
             }
         }
         Provider::GitHub => {
-            if !verify_github(req.headers(), &project.token, &body) {
+            if !verify_github(req.headers(), project.token.expose(), &body) {
                 warn!(log, "X-Hub-Signature-256 header verification failed");
-                return Ok(HttpResponse::Unauthorized().into());
+                return Err(ApiError::Unauthorized("invalid signature"));
             }
 
             debug!(log, "X-Hub-Signature-256 header verified");
-            let webhook = serde_json::from_slice::<GitHubWebhook>(&body)?;
+            let event_name = github_event(req.headers());
+            let webhook = match GitHubEvent::parse(&event_name, &body)? {
+                Some(webhook) => webhook,
+                None => {
+                    debug!(log, "ignoring unsupported github event"; "event" => &event_name);
+                    return Ok(api::ignored(format!(
+                        "unsupported github event {}",
+                        event_name
+                    )));
+                }
+            };
             webhook.dump();
 
-            if github::should_deploy_github(
-                webhook.action(),
-                webhook.is_merged(),
-                webhook.target_branch(),
-            ) {
-                match webhook.clone_repository() {
-                    Ok(repo_path) => debug!(log, "cloned repository"; "path" => repo_path),
-                    Err(e) => error!(log, "failed to clone"; "error" => e),
-                }
-                let project_clone = project.clone();
-                task::spawn(async move { data.execute_commands(project_clone).await });
+            let event = webhook.event();
+            if project.should_deploy(&event) {
+                let record = job_record(&project, &event, webhook.sha(), "webhook");
+                let env = webhook.env();
+                let checkouts = checkouts(&settings, &project);
+                let source = webhook.git_source();
+                let id = enqueue(
+                    &queue,
+                    store,
+                    registry,
+                    config,
+                    project,
+                    record,
+                    env,
+                    move || checkouts.checkout(&source),
+                )?;
+                return Ok(queued(&req, id));
             }
         }
     }
 
-    Ok(HttpResponse::Ok().into())
+    Ok(api::ignored(
+        "the event doesn't match the deploy rules of the project".to_string(),
+    ))
 }
 
-#[get("/trigger/{project_name}")]
-async fn trigger(
-    data: web::Data<Config>,
+#[post("/projects/{project_name}/deploy")]
+#[allow(clippy::too_many_arguments)]
+async fn deploy(
+    data: web::Data<SharedConfig>,
+    settings: web::Data<ServerSettings>,
+    queue: web::Data<Queue>,
+    store: web::Data<Store>,
+    registry: web::Data<Registry>,
+    req: HttpRequest,
     web::Path(project_name): web::Path<String>,
-    info: web::Query<TriggerInfo>,
-) -> Result<HttpResponse, Error> {
+    info: web::Json<DeployRequest>,
+) -> Result<HttpResponse, ApiError> {
     let log = slog_scope::logger();
-    let project = data.get_project(project_name.clone()).unwrap();
-    debug!(log, "trigger project"; "project" => project_name.clone(), "repo" => info.repo.clone(), "provider" => format!("{:?}", project.provider));
+    let config = data.get();
+    let project = match config.get_project(project_name.clone()) {
+        Some(project) => project,
+        None => return Err(ApiError::ProjectNotFound(project_name)),
+    };
+    verify_deploy(req.headers(), &project, &config)?;
+    let repository = match &project.git.repository {
+        Some(repository) => repository.clone(),
+        None => {
+            return Err(ApiError::BadRequest(format!(
+                "project {} has no repository configured",
+                project_name
+            )))
+        }
+    };
 
-    match project.provider {
-        Provider::GitLab => {
-            let input = format!(
-                r#"{{
-                    "event_type": "merge_request",
-                    "project": {{
-                        "default_branch": "main",
-                        "git_http_url": "{}",
-                        "path_with_namespace": "{}"
-                    }},
-                    "repository": {{
-                        "url": "{}"
-                    }},
-                    "object_attributes": {{
-                        "action": "merge",
-                        "target_branch": "main",
-                        "source_branch": "staging",
-                        "state": "merge",
-                        "merge_status": "merged"
-                    }}
-                }}"#,
-                info.repo.clone(),
-                info.path.clone(),
-                info.repo.clone()
-            );
-            let webhook = serde_json::from_str::<GitLabWebhook>(&input).unwrap();
-            webhook.dump();
+    let info = info.into_inner();
+    let sha = info.sha;
+    if let Some(sha) = &sha {
+        if !valid_sha(sha) {
+            return Err(ApiError::BadRequest(format!(
+                "sha {} isn't a hexadecimal commit id",
+                sha
+            )));
+        }
+    }
+    // a commit without a branch is looked up in every branch
+    let branch = match (info.r#ref, &sha) {
+        (Some(branch), _) => Some(branch),
+        (None, Some(_)) => None,
+        (None, None) => {
+            let credentials = project.credentials();
+            let url = repository.clone();
+            let branch =
+                async_std::task::spawn_blocking(move || git::default_branch(&url, &credentials));
+            match branch.await {
+                Ok(branch) => Some(branch),
+                Err(e) => return Err(ApiError::Unavailable(e.to_string())),
+            }
+        }
+    };
+    info!(log, "manual deployment";
+        "project" => &project_name,
+        "repository" => &repository,
+        "ref" => format!("{:?}", branch),
+        "sha" => format!("{:?}", sha),
+    );
+
+    let webhook = match project.provider {
+        Provider::GitLab => ManualEvent::GitLab(GitLabEvent::Push(GitLabPush::manual(
+            &repository,
+            branch.as_deref(),
+            sha.as_deref(),
+        ))),
+        Provider::GitHub => ManualEvent::GitHub(GitHubEvent::Push(GitHubPush::manual(
+            &repository,
+            branch.as_deref(),
+            sha.as_deref(),
+        ))),
+    };
 
-            if config::should_deploy(
-                webhook.target_branch(),
-                webhook.action(),
-                webhook.merge_status(),
-            ) {
-                debug!(log, "handle deployment"; "project" => project_name);
-                match webhook.clone_repository() {
-                    Ok(repo_path) => debug!(log, "cloned repository"; "path" => repo_path),
-                    Err(e) => error!(log, "failed to clone"; "error" => e),
+    let (event, sha_value, env, mut source) = match &webhook {
+        ManualEvent::GitLab(event) => (event.event(), event.sha(), event.env(), event.git_source()),
+        ManualEvent::GitHub(event) => (event.event(), event.sha(), event.env(), event.git_source()),
+    };
+    if branch.is_none() {
+        source.reference = Ref::Commit;
+    }
+    let record = job_record(&project, &event, sha_value, "manual");
+    let checkouts = checkouts(&settings, &project);
+    let id = enqueue(
+        &queue,
+        store,
+        registry,
+        config,
+        project,
+        record,
+        env,
+        move || checkouts.checkout(&source),
+    )?;
+    Ok(queued(&req, id))
+}
+
+#[get("/jobs")]
+async fn list_jobs(
+    data: web::Data<SharedConfig>,
+    store: web::Data<Store>,
+    req: HttpRequest,
+    query: web::Query<JobsQuery>,
+) -> Result<HttpResponse, ApiError> {
+    verify_jobs(req.headers(), query.project.as_deref(), &data.get())?;
+    let limit = query.limit.unwrap_or(JOBS_LIMIT).min(JOBS_LIMIT_MAX);
+    let jobs = store.list(query.project.as_deref(), limit)?;
+    Ok(HttpResponse::Ok().json(jobs))
+}
+
+#[get("/jobs/{id}")]
+async fn show_job(
+    data: web::Data<SharedConfig>,
+    store: web::Data<Store>,
+    req: HttpRequest,
+    web::Path(id): web::Path<i64>,
+) -> Result<HttpResponse, ApiError> {
+    verify_jobs(req.headers(), store.project(id)?.as_deref(), &data.get())?;
+    match store.get(id)? {
+        Some(job) => Ok(HttpResponse::Ok().json(job)),
+        None => Err(ApiError::JobNotFound(id)),
+    }
+}
+
+#[get("/jobs/{id}/status")]
+async fn job_status(
+    data: web::Data<SharedConfig>,
+    store: web::Data<Store>,
+    req: HttpRequest,
+    web::Path(id): web::Path<i64>,
+) -> Result<HttpResponse, ApiError> {
+    verify_jobs(req.headers(), store.project(id)?.as_deref(), &data.get())?;
+    match store.status(id)? {
+        Some(status) => Ok(HttpResponse::Ok().json(JobStatus { id, status })),
+        None => Err(ApiError::JobNotFound(id)),
+    }
+}
+
+#[post("/jobs/{id}/cancel")]
+async fn cancel_job(
+    data: web::Data<SharedConfig>,
+    store: web::Data<Store>,
+    registry: web::Data<Registry>,
+    req: HttpRequest,
+    web::Path(id): web::Path<i64>,
+) -> Result<HttpResponse, ApiError> {
+    let log = slog_scope::logger();
+    verify_admin(req.headers(), &data.get())?;
+
+    let job = match registry.cancel(id) {
+        Some(job) => job,
+        None => {
+            return match store.status(id)? {
+                Some(status) => Ok(HttpResponse::Conflict().json(JobStatus { id, status })),
+                None => Err(ApiError::JobNotFound(id)),
+            };
+        }
+    };
+
+    let mut job = job.lock().unwrap();
+    info!(log, "cancel job"; "id" => id, "project" => &job.project, "status" => format!("{:?}", job.status));
+    if job.status == Status::Queued {
+        job.cancel(None);
+        store.cancel(id)?;
+    }
+    Ok(HttpResponse::Accepted().json(JobStatus {
+        id,
+        status: job.status,
+    }))
+}
+
+/// Render a server-sent event.
+fn sse<T: Serialize>(event: &str, data: &T) -> String {
+    let data = serde_json::to_string(data).unwrap_or_else(|_| "null".to_string());
+    format!("event: {}\ndata: {}\n\n", event, data)
+}
+
+/// Stream of server-sent events with the output of the job, the output that's
+/// already there is replayed first. The stream ends with a `status` event once
+/// the job has finished.
+fn log_stream(id: i64, job: JobHandle) -> impl futures::Stream<Item = Result<web::Bytes, Error>> {
+    futures::stream::unfold(
+        (job, Cursor::default(), false),
+        move |(job, mut cursor, done)| async move {
+            if done {
+                return None;
+            }
+            loop {
+                let (events, status) = {
+                    let job = job.lock().unwrap();
+                    (
+                        job.log_since(&mut cursor),
+                        job.is_finished().then(|| job.status),
+                    )
+                };
+                if events.is_empty() && status.is_none() {
+                    async_std::task::sleep(LOG_POLL).await;
+                    continue;
+                }
+
+                let mut chunk = events
+                    .iter()
+                    .map(|event| sse(event.name(), event))
+                    .collect::<String>();
+                if let Some(status) = status {
+                    chunk.push_str(&sse("status", &JobStatus { id, status }));
                 }
-                let project_clone = project.clone();
-                task::spawn(async move { data.execute_commands(project_clone).await });
-                Ok(HttpResponse::Ok().into())
-            } else {
-                Ok(HttpResponse::InternalServerError().into())
+                let done = status.is_some();
+                return Some((Ok(web::Bytes::from(chunk)), (job, cursor, done)));
             }
-        }
-        Provider::GitHub => {
-            // Extract repo name from path (last part)
-            let repo_name = info.path.split('/').last().unwrap_or(&info.path);
-            let input = format!(
-                r#"{{
-                    "action": "closed",
-                    "repository": {{
-                        "name": "{}",
-                        "full_name": "{}",
-                        "clone_url": "{}",
-                        "ssh_url": "{}",
-                        "default_branch": "main"
-                    }},
-                    "pull_request": {{
-                        "number": 999,
-                        "state": "closed",
-                        "title": "Test PR",
-                        "merged": true,
-                        "merged_at": "2024-01-01T00:00:00Z",
-                        "head": {{
-                            "ref": "feature-branch",
-                            "sha": "abc123"
-                        }},
-                        "base": {{
-                            "ref": "main",
-                            "sha": "def456"
-                        }}
-                    }},
-                    "sender": {{
-                        "login": "test-trigger"
-                    }}
-                }}"#,
-                repo_name,
-                info.path.clone(),
-                info.repo.clone(),
-                info.repo.clone()
-            );
-            let webhook = serde_json::from_str::<GitHubWebhook>(&input).unwrap();
-            webhook.dump();
+        },
+    )
+}
+
+#[get("/jobs/{id}/logs")]
+async fn job_logs(
+    data: web::Data<SharedConfig>,
+    store: web::Data<Store>,
+    registry: web::Data<Registry>,
+    req: HttpRequest,
+    web::Path(id): web::Path<i64>,
+) -> Result<HttpResponse, ApiError> {
+    verify_jobs(req.headers(), store.project(id)?.as_deref(), &data.get())?;
+    let job = match registry.get(id) {
+        Some(job) => job,
+        None => match store.get(id)? {
+            Some(job) => job.handle(),
+            None => return Err(ApiError::JobNotFound(id)),
+        },
+    };
 
-            if github::should_deploy_github(
-                webhook.action(),
-                webhook.is_merged(),
-                webhook.target_branch(),
-            ) {
-                debug!(log, "handle deployment"; "project" => project_name);
-                match webhook.clone_repository() {
-                    Ok(repo_path) => debug!(log, "cloned repository"; "path" => repo_path),
-                    Err(e) => error!(log, "failed to clone"; "error" => e),
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .header("Cache-Control", "no-cache")
+        .streaming(Box::pin(log_stream(id, job))))
+}
+
+/// Acceptor for the certificate and key of the TLS settings.
+#[cfg(feature = "tls")]
+fn tls_acceptor(tls: &config::TlsSettings) -> io::Result<openssl::ssl::SslAcceptorBuilder> {
+    use openssl::ssl::{SslAcceptor, SslFiletype, SslMethod};
+
+    let mut builder = SslAcceptor::mozilla_intermediate(SslMethod::tls())?;
+    builder.set_private_key_file(&tls.key, SslFiletype::PEM)?;
+    builder.set_certificate_chain_file(&tls.cert)?;
+    Ok(builder)
+}
+
+/// Report the problems of the configuration at `path` and exit non-zero when
+/// there are any, secrets are only read when `resolve` is set.
+fn check_config(path: &str, resolve: bool) {
+    let config = match resolve {
+        true => Config::load(path),
+        false => Config::load_unresolved(path),
+    };
+    match config {
+        Ok(_) => println!("{}: ok", path),
+        Err(e) => {
+            for problem in e.problems() {
+                match (&problem.file, problem.position) {
+                    (Some(_), _) => eprintln!("{}", problem),
+                    (None, Some(_)) => eprintln!("{}:{}", path, problem),
+                    (None, None) => eprintln!("{}: {}", path, problem),
                 }
-                let project_clone = project.clone();
-                task::spawn(async move { data.execute_commands(project_clone).await });
-                Ok(HttpResponse::Ok().into())
-            } else {
-                Ok(HttpResponse::InternalServerError().into())
             }
+            std::process::exit(1);
         }
     }
 }
@@ -243,35 +706,173 @@ async fn trigger(
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     let shook = ShookArgs::new();
+    if shook.check_config {
+        check_config(&shook.config, shook.resolve_secrets);
+        return Ok(());
+    }
+    let config = Config::load(&shook.config).map_err(|e| io::Error::other(e.to_string()))?;
+    let env = ServerSettings::from_env().map_err(|problems| {
+        let problems = problems.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+        io::Error::other(problems.join(", "))
+    })?;
+    let settings = shook.server().or(env).or(config.server.clone());
+
     let drain = slog_json::Json::new(std::io::stdout())
         .set_pretty(false)
         .add_default_keys()
         .build()
         .fuse();
     let drain = slog_async::Async::new(drain).build().fuse();
-    let drain = slog::LevelFilter(drain, shook.level).fuse();
+    let drain = slog::LevelFilter(drain, settings.log_level()).fuse();
     let log = slog::Logger::root(drain, o!());
     let _guard = slog_scope::set_global_logger(log);
 
-    let config_file = std::fs::File::open(shook.config)?;
-    let config: Config = serde_yaml::from_reader(config_file).unwrap();
-
     let logger = slog_scope::logger();
-    let app_log = logger.new(o!("host" => shook.host.clone(), "port" => shook.port.clone()));
+    let app_log = logger.new(o!("host" => settings.host().to_string(), "port" => settings.port()));
     info!(app_log, "application started"; "started_at" => format!("{}", Utc::now()));
 
-    let config_data = web::Data::new(config);
+    if let Some(dir) = Path::new(&shook.database).parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let store = Store::open(&shook.database).map_err(|e| io::Error::other(e.to_string()))?;
+    // jobs that were queued or running when shook stopped never finish
+    match store.interrupt() {
+        Ok(0) => (),
+        Ok(count) => warn!(app_log, "finished jobs of the previous run"; "jobs" => count),
+        Err(e) => return Err(io::Error::other(e.to_string())),
+    }
+    let store_data = web::Data::new(store);
+    let registry_data = web::Data::new(Registry::default());
+    let queue_data = web::Data::new(Queue::new(config.queue.concurrency, config.queue.capacity));
+    let shared = Arc::new(SharedConfig::new(config));
+    reload::on_signal(PathBuf::from(&shook.config), shared.clone())?;
+    if shook.watch {
+        reload::watch(PathBuf::from(&shook.config), shared.clone(), WATCH_INTERVAL);
+    }
+    let config_data = web::Data::from(shared);
+    let settings_data = web::Data::new(settings.clone());
 
-    HttpServer::new(move || {
+    let server = HttpServer::new(move || {
         App::new()
             .wrap(StructuredLogger::new(
                 logger.new(o!("version" => "undefined")),
             ))
+            .app_data(api::json_config())
+            .app_data(api::query_config())
+            .app_data(api::path_config())
             .app_data(config_data.clone())
+            .app_data(settings_data.clone())
+            .app_data(queue_data.clone())
+            .app_data(store_data.clone())
+            .app_data(registry_data.clone())
             .service(webhook_handler)
-            .service(trigger)
-    })
-    .bind(format!("{}:{}", shook.host, shook.port))?
-    .run()
-    .await
+            .service(deploy)
+            .service(list_jobs)
+            .service(show_job)
+            .service(job_status)
+            .service(job_logs)
+            .service(cancel_job)
+    });
+    let server = match settings.workers {
+        Some(workers) => server.workers(workers),
+        None => server,
+    };
+    let address = format!("{}:{}", settings.host(), settings.port());
+    let server = match &settings.tls {
+        #[cfg(feature = "tls")]
+        Some(tls) => server.bind_openssl(&address, tls_acceptor(tls)?)?,
+        #[cfg(not(feature = "tls"))]
+        Some(_) => {
+            return Err(io::Error::other(
+                "TLS requires shook to be built with the `tls` feature",
+            ))
+        }
+        None => server.bind(&address)?,
+    };
+    server.run().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::header::{HeaderName, HeaderValue};
+
+    #[test]
+    fn it_keeps_checkouts_of_projects_apart() {
+        let config = Config::parse(
+            "projects:
+  - name: staging
+    token: secret
+    commands: [make]
+  - name: production
+    token: secret
+    commands: [make]
+",
+        )
+        .unwrap();
+        let settings = ServerSettings::default();
+        let path = |name: &str| {
+            let project = config.get_project(name.to_string()).unwrap();
+            checkouts(&settings, &project).path("org/api").unwrap()
+        };
+
+        assert_eq!(
+            path("staging"),
+            PathBuf::from("/var/cache/shook/staging/org/api")
+        );
+        assert_ne!(path("staging"), path("production"));
+    }
+
+    #[test]
+    fn it_only_accepts_commit_ids() {
+        assert!(valid_sha("0a1b2c3d"));
+        assert!(valid_sha(&"f".repeat(40)));
+        assert!(!valid_sha("abc"));
+        assert!(!valid_sha("HEAD~1"));
+        assert!(!valid_sha("main"));
+        assert!(!valid_sha(&"f".repeat(41)));
+    }
+
+    #[test]
+    fn it_limits_project_tokens_to_their_jobs() {
+        let config = Config::parse(
+            "admin_token: admin
+projects:
+  - name: web
+    token: web-token
+    commands: [make]
+  - name: api
+    token: api-token
+    commands: [make]
+",
+        )
+        .unwrap();
+        let headers = |token: &'static str| {
+            let mut headers = HeaderMap::new();
+            let value = HeaderValue::from_str(&format!("Bearer {}", token)).unwrap();
+            headers.insert(HeaderName::from_static("authorization"), value);
+            headers
+        };
+
+        assert!(verify_jobs(&headers("admin"), None, &config).is_ok());
+        assert!(verify_jobs(&headers("admin"), Some("api"), &config).is_ok());
+        assert!(verify_jobs(&headers("web-token"), Some("web"), &config).is_ok());
+        assert!(verify_jobs(&headers("web-token"), Some("api"), &config).is_err());
+        assert!(verify_jobs(&headers("web-token"), None, &config).is_err());
+        assert!(verify_jobs(&HeaderMap::new(), Some("web"), &config).is_err());
+    }
+
+    #[test]
+    fn it_refuses_invalid_gitlab_tokens() {
+        let mut headers = HeaderMap::new();
+        assert!(!verify_gitlab(&headers, "secret"));
+
+        let name = HeaderName::from_static("x-gitlab-token");
+        headers.insert(name.clone(), HeaderValue::from_static("secret"));
+        assert!(verify_gitlab(&headers, "secret"));
+
+        // header values that aren't visible ASCII
+        headers.insert(name, HeaderValue::from_bytes(b"gl\xe9").unwrap());
+        assert!(!verify_gitlab(&headers, "secret"));
+    }
 }
@@ -0,0 +1,256 @@
+use async_std::channel::{self, Receiver, Sender};
+use async_std::task;
+use futures::future::BoxFuture;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+/// Work that's run for a single deployment.
+pub type Task = BoxFuture<'static, ()>;
+
+#[derive(Debug, PartialEq)]
+pub enum QueueError {
+    /// The project already has the maximum number of pending jobs.
+    Full(String),
+}
+
+impl fmt::Display for QueueError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            QueueError::Full(project) => write!(f, "job queue for {} is full", project),
+        }
+    }
+}
+
+/// Jobs of a single project, only one of them runs at a time.
+#[derive(Default)]
+struct Lane {
+    running: bool,
+    pending: VecDeque<(i64, Task)>,
+}
+
+struct Inner {
+    lanes: Mutex<HashMap<String, Lane>>,
+    permits: (Sender<()>, Receiver<()>),
+    capacity: usize,
+}
+
+/// Job queue that runs the jobs of a project one after the other, with at most
+/// `concurrency` jobs running across all projects.
+#[derive(Clone)]
+pub struct Queue {
+    inner: Arc<Inner>,
+}
+
+impl Queue {
+    /// Create a queue that runs up to `concurrency` jobs at once and keeps up to
+    /// `capacity` pending jobs per project, `concurrency` has to be at least 1.
+    pub fn new(concurrency: usize, capacity: usize) -> Self {
+        let (sender, receiver) = channel::bounded(concurrency);
+        for _ in 0..concurrency {
+            sender.try_send(()).expect("permit channel has capacity");
+        }
+
+        Queue {
+            inner: Arc::new(Inner {
+                lanes: Mutex::new(HashMap::new()),
+                permits: (sender, receiver),
+                capacity,
+            }),
+        }
+    }
+
+    /// Add the job with the given id for the project. When `coalesce` is set
+    /// the jobs that are still pending for the project are dropped, so only the
+    /// newest one runs after the current job. The ids of dropped jobs are
+    /// returned, no job is dropped when the new one is rejected.
+    pub fn submit(
+        &self,
+        project: &str,
+        id: i64,
+        coalesce: bool,
+        task: Task,
+    ) -> Result<Vec<i64>, QueueError> {
+        let log = slog_scope::logger();
+        let mut lanes = self.inner.lanes.lock().unwrap();
+        let lane = lanes.entry(project.to_string()).or_default();
+
+        if lane.running {
+            let kept = match coalesce {
+                true => 0,
+                false => lane.pending.len(),
+            };
+            if kept >= self.inner.capacity {
+                return Err(QueueError::Full(project.to_string()));
+            }
+            let mut dropped = Vec::new();
+            if coalesce && !lane.pending.is_empty() {
+                debug!(log, "queue coalesced pending jobs";
+                    "project" => project,
+                    "dropped" => lane.pending.len(),
+                );
+                dropped = lane.pending.drain(..).map(|(id, _)| id).collect();
+            }
+            lane.pending.push_back((id, task));
+            debug!(log, "queue job pending"; "project" => project, "pending" => lane.pending.len());
+            return Ok(dropped);
+        }
+
+        lane.running = true;
+        task::spawn(self.clone().run(project.to_string(), task));
+        Ok(Vec::new())
+    }
+
+    /// Number of jobs waiting for the project, not counting the running job.
+    pub fn pending(&self, project: &str) -> usize {
+        match self.inner.lanes.lock().unwrap().get(project) {
+            None => 0,
+            Some(lane) => lane.pending.len(),
+        }
+    }
+
+    async fn run(self, project: String, mut task: Task) {
+        let log = slog_scope::logger();
+        let (release, acquire) = &self.inner.permits;
+
+        loop {
+            // the sender is held by the queue so the channel can't be closed
+            acquire.recv().await.expect("permit channel is open");
+            debug!(log, "queue job started"; "project" => &project);
+            task.await;
+            debug!(log, "queue job finished"; "project" => &project);
+            release.send(()).await.expect("permit channel is open");
+
+            let mut lanes = self.inner.lanes.lock().unwrap();
+            let lane = lanes.entry(project.clone()).or_default();
+            match lane.pending.pop_front() {
+                Some((_, next)) => task = next,
+                None => {
+                    lanes.remove(&project);
+                    return;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::FutureExt;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    /// Task that records its id once it has run, the running count is used to
+    /// track how many tasks overlap.
+    fn record(
+        id: usize,
+        log: Arc<Mutex<Vec<usize>>>,
+        running: Arc<AtomicUsize>,
+        peak: Arc<AtomicUsize>,
+    ) -> Task {
+        async move {
+            let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+            peak.fetch_max(now, Ordering::SeqCst);
+            task::sleep(Duration::from_millis(20)).await;
+            running.fetch_sub(1, Ordering::SeqCst);
+            log.lock().unwrap().push(id);
+        }
+        .boxed()
+    }
+
+    async fn wait_for(log: &Arc<Mutex<Vec<usize>>>, count: usize) {
+        while log.lock().unwrap().len() < count {
+            task::sleep(Duration::from_millis(5)).await;
+        }
+    }
+
+    #[test]
+    fn it_serializes_jobs_per_project() {
+        task::block_on(async {
+            let queue = Queue::new(4, 8);
+            let log = Arc::new(Mutex::new(Vec::new()));
+            let running = Arc::new(AtomicUsize::new(0));
+            let peak = Arc::new(AtomicUsize::new(0));
+
+            for id in 0..3 {
+                let task = record(id, log.clone(), running.clone(), peak.clone());
+                queue.submit("sample", id as i64, false, task).unwrap();
+            }
+            assert_eq!(queue.pending("sample"), 2);
+
+            wait_for(&log, 3).await;
+            assert_eq!(*log.lock().unwrap(), vec![0, 1, 2]);
+            assert_eq!(peak.load(Ordering::SeqCst), 1);
+        });
+    }
+
+    #[test]
+    fn it_limits_concurrency() {
+        task::block_on(async {
+            let queue = Queue::new(2, 8);
+            let log = Arc::new(Mutex::new(Vec::new()));
+            let running = Arc::new(AtomicUsize::new(0));
+            let peak = Arc::new(AtomicUsize::new(0));
+
+            for id in 0..4 {
+                let task = record(id, log.clone(), running.clone(), peak.clone());
+                queue
+                    .submit(&format!("project-{}", id), id as i64, false, task)
+                    .unwrap();
+            }
+
+            wait_for(&log, 4).await;
+            assert_eq!(peak.load(Ordering::SeqCst), 2);
+        });
+    }
+
+    #[test]
+    fn it_coalesces_pending_jobs() {
+        task::block_on(async {
+            let queue = Queue::new(1, 8);
+            let log = Arc::new(Mutex::new(Vec::new()));
+            let running = Arc::new(AtomicUsize::new(0));
+            let peak = Arc::new(AtomicUsize::new(0));
+
+            let mut dropped = Vec::new();
+            for id in 0..4 {
+                let task = record(id, log.clone(), running.clone(), peak.clone());
+                dropped.extend(queue.submit("sample", id as i64, true, task).unwrap());
+            }
+            assert_eq!(queue.pending("sample"), 1);
+            assert_eq!(dropped, vec![1, 2]);
+
+            wait_for(&log, 2).await;
+            assert_eq!(*log.lock().unwrap(), vec![0, 3]);
+        });
+    }
+
+    #[test]
+    fn it_rejects_jobs_when_full() {
+        task::block_on(async {
+            let queue = Queue::new(1, 1);
+            let log = Arc::new(Mutex::new(Vec::new()));
+            let running = Arc::new(AtomicUsize::new(0));
+            let peak = Arc::new(AtomicUsize::new(0));
+
+            let first = record(0, log.clone(), running.clone(), peak.clone());
+            let second = record(1, log.clone(), running.clone(), peak.clone());
+            let third = record(2, log.clone(), running.clone(), peak.clone());
+            let fourth = record(3, log.clone(), running.clone(), peak.clone());
+
+            assert!(queue.submit("sample", 0, false, first).is_ok());
+            assert!(queue.submit("sample", 1, false, second).is_ok());
+            assert_eq!(
+                queue.submit("sample", 2, false, third),
+                Err(QueueError::Full("sample".to_string()))
+            );
+            assert_eq!(queue.pending("sample"), 1);
+            // coalescing makes room for the job
+            assert_eq!(queue.submit("sample", 3, true, fourth), Ok(vec![1]));
+
+            wait_for(&log, 2).await;
+            assert_eq!(*log.lock().unwrap(), vec![0, 3]);
+        });
+    }
+}
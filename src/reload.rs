@@ -0,0 +1,122 @@
+use signal_hook::consts::SIGHUP;
+use signal_hook::iterator::Signals;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use crate::config::{self, Config, ConfigError, SharedConfig};
+
+/// Load the configuration file into `shared`, the running configuration is
+/// kept when the file fails to load.
+pub fn reload(path: &Path, shared: &SharedConfig) -> Result<(), ConfigError> {
+    let log = slog_scope::logger();
+    let config = Config::load(path)?;
+
+    if config.queue != shared.get().queue {
+        warn!(
+            log,
+            "queue settings changed, they are applied after a restart"
+        );
+    }
+    if config.server != shared.get().server {
+        warn!(
+            log,
+            "server settings changed, they are applied after a restart"
+        );
+    }
+    shared.replace(config);
+    Ok(())
+}
+
+fn reload_logged(path: &Path, shared: &SharedConfig, reason: &str) {
+    let log = slog_scope::logger();
+
+    match reload(path, shared) {
+        Ok(()) => {
+            info!(log, "configuration reloaded"; "path" => path.display().to_string(), "reason" => reason)
+        }
+        Err(e) => {
+            error!(log, "failed to reload configuration, keeping the running one";
+                "path" => path.display().to_string(),
+                "reason" => reason,
+                "error" => e.to_string(),
+            )
+        }
+    }
+}
+
+/// Reload the configuration whenever the process receives `SIGHUP`.
+pub fn on_signal(path: PathBuf, shared: Arc<SharedConfig>) -> io::Result<()> {
+    let mut signals = Signals::new([SIGHUP])?;
+
+    thread::spawn(move || {
+        for _ in signals.forever() {
+            reload_logged(&path, &shared, "SIGHUP");
+        }
+    });
+    Ok(())
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+/// Modification times of the configuration, for a directory they include the
+/// directory itself so added and removed files are noticed.
+fn modified_all(path: &Path) -> Option<Vec<Option<SystemTime>>> {
+    let mut times = vec![Some(modified(path)?)];
+    if path.is_dir() {
+        times.push(modified(&path.join(config::GLOBAL_FILE)));
+        let files = config::project_files(path).ok()?;
+        times.extend(files.iter().map(|file| modified(file)));
+    }
+    Some(times)
+}
+
+/// Reload the configuration when the modification time of the file, or of a
+/// file of the configuration directory, changes. It's checked every
+/// `interval`.
+pub fn watch(path: PathBuf, shared: Arc<SharedConfig>, interval: Duration) {
+    thread::spawn(move || {
+        let mut last = modified_all(&path);
+        loop {
+            thread::sleep(interval);
+            let current = modified_all(&path);
+            if current.is_some() && current != last {
+                last = current;
+                reload_logged(&path, &shared, "file changed");
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_keeps_config_when_reload_fails() {
+        let path = std::env::temp_dir().join(format!("shook-reload-{}.yml", std::process::id()));
+        let project = |name: &str| {
+            format!(
+                "projects:\n  - name: {}\n    token: secret\n    commands: [make]\n",
+                name
+            )
+        };
+        fs::write(&path, project("sample")).unwrap();
+        let shared = SharedConfig::new(Config::load(&path).unwrap());
+
+        fs::write(&path, project("other")).unwrap();
+        reload(&path, &shared).unwrap();
+        assert!(shared.get().get_project("other".to_string()).is_some());
+
+        fs::write(&path, "projects: [").unwrap();
+        assert!(matches!(reload(&path, &shared), Err(ConfigError::Parse(_))));
+        assert!(shared.get().get_project("other".to_string()).is_some());
+
+        fs::remove_file(&path).unwrap();
+    }
+}
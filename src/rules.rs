@@ -0,0 +1,280 @@
+use glob::Pattern;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// Provider independent view of a webhook event that deploy rules are
+/// evaluated against.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Event {
+    /// Name of the event, eg. `merge_request`, `pull_request` or `push`.
+    pub kind: String,
+    pub branch: String,
+    pub tag: String,
+    pub action: String,
+    pub state: String,
+    /// Workflow or check suite app name for CI result events.
+    pub workflow: String,
+}
+
+/// GitHub events that report the result of a CI run, these only deploy once
+/// the run has completed successfully.
+const CI_EVENTS: [&str; 2] = ["workflow_run", "check_suite"];
+
+/// GitLab event that reports the status of a pipeline, it only deploys once
+/// the pipeline has succeeded.
+const PIPELINE_EVENT: &str = "pipeline";
+
+/// A single deploy rule, every list that is set must contain a match for the
+/// rule to apply. An empty or missing list accepts any value.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    #[serde(default)]
+    pub events: Vec<String>,
+    /// Glob patterns matched against the target branch, eg. `release/*`.
+    #[serde(default)]
+    pub branches: Vec<String>,
+    /// Glob patterns matched against the tag name, eg. `v*`.
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Glob patterns matched against the workflow name of CI result events.
+    #[serde(default)]
+    pub workflows: Vec<String>,
+    #[serde(default)]
+    pub actions: Vec<String>,
+    #[serde(default)]
+    pub states: Vec<String>,
+}
+
+/// Whether a pattern matches the value, the patterns are checked when the
+/// configuration is loaded so invalid ones never match.
+fn matches_glob(patterns: &[String], value: &str) -> bool {
+    patterns.is_empty()
+        || patterns
+            .iter()
+            .any(|pattern| Pattern::new(pattern).is_ok_and(|glob| glob.matches(value)))
+}
+
+fn matches_any(values: &[String], value: &str) -> bool {
+    values.is_empty() || values.iter().any(|item| item == value)
+}
+
+impl Event {
+    /// Environment variables describing the event that are passed to the
+    /// project commands, values that are empty are left out.
+    pub fn env(&self) -> HashMap<String, String> {
+        let mut env = HashMap::new();
+        let values = [
+            ("SHOOK_EVENT", &self.kind),
+            ("SHOOK_BRANCH", &self.branch),
+            ("SHOOK_TAG", &self.tag),
+            ("SHOOK_ACTION", &self.action),
+            ("SHOOK_STATE", &self.state),
+            ("SHOOK_WORKFLOW", &self.workflow),
+        ];
+        for (name, value) in values.iter() {
+            if !value.is_empty() {
+                env.insert(name.to_string(), value.to_string());
+            }
+        }
+        env
+    }
+
+    /// CI result events must have completed with the conclusion `success`
+    /// and pipelines must have the status `success`, every other event passes.
+    pub fn is_successful(&self) -> bool {
+        match self.kind.as_str() {
+            PIPELINE_EVENT => self.state == "success",
+            kind if CI_EVENTS.contains(&kind) => {
+                self.action == "completed" && self.state == "success"
+            }
+            _ => true,
+        }
+    }
+}
+
+impl Rule {
+    pub fn new(events: &[&str], branches: &[&str], actions: &[&str], states: &[&str]) -> Self {
+        let to_vec = |items: &[&str]| items.iter().map(|item| item.to_string()).collect();
+        Rule {
+            events: to_vec(events),
+            branches: to_vec(branches),
+            tags: Vec::new(),
+            workflows: Vec::new(),
+            actions: to_vec(actions),
+            states: to_vec(states),
+        }
+    }
+
+    /// Whether the rule has no conditions, it would match every event.
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+            && self.branches.is_empty()
+            && self.tags.is_empty()
+            && self.workflows.is_empty()
+            && self.actions.is_empty()
+            && self.states.is_empty()
+    }
+
+    /// Glob patterns that don't compile, with the field and index they're at,
+    /// eg. `branches[1]`, and the reason.
+    pub fn invalid_patterns(&self) -> Vec<(String, String)> {
+        let fields = [
+            ("branches", &self.branches),
+            ("tags", &self.tags),
+            ("workflows", &self.workflows),
+        ];
+        let mut invalid = Vec::new();
+        for (field, patterns) in fields {
+            for (index, pattern) in patterns.iter().enumerate() {
+                if let Err(e) = Pattern::new(pattern) {
+                    invalid.push((
+                        format!("{}[{}]", field, index),
+                        format!("`{}` isn't a valid glob pattern: {}", pattern, e.msg),
+                    ));
+                }
+            }
+        }
+        invalid
+    }
+
+    pub fn matches(&self, event: &Event) -> bool {
+        matches_any(&self.events, &event.kind)
+            && matches_glob(&self.branches, &event.branch)
+            && matches_glob(&self.tags, &event.tag)
+            && matches_glob(&self.workflows, &event.workflow)
+            && matches_any(&self.actions, &event.action)
+            && matches_any(&self.states, &event.state)
+    }
+}
+
+/// Check if any of the rules accepts the event.
+pub fn should_deploy(rules: &[Rule], event: &Event) -> bool {
+    event.is_successful() && rules.iter().any(|rule| rule.matches(event))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(branch: &str, action: &str, state: &str) -> Event {
+        Event {
+            kind: "merge_request".to_string(),
+            branch: branch.to_string(),
+            action: action.to_string(),
+            state: state.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn it_matches_rule() {
+        let rule = Rule::new(&[], &["main"], &["merge"], &["merged"]);
+
+        assert!(rule.matches(&event("main", "merge", "merged")));
+        assert!(!rule.matches(&event("staging", "merge", "merged")));
+        assert!(!rule.matches(&event("main", "open", "merged")));
+        assert!(!rule.matches(&event("main", "merge", "closed")));
+    }
+
+    #[test]
+    fn it_matches_branch_globs() {
+        let rule = Rule::new(&[], &["master", "release/*", "production"], &[], &[]);
+
+        assert!(rule.matches(&event("master", "merge", "merged")));
+        assert!(rule.matches(&event("release/1.2", "closed", "merged")));
+        assert!(rule.matches(&event("production", "", "")));
+        assert!(!rule.matches(&event("main", "merge", "merged")));
+        assert!(!rule.matches(&event("release", "merge", "merged")));
+    }
+
+    #[test]
+    fn it_matches_events() {
+        let rule = Rule::new(&["push"], &["main"], &[], &[]);
+        let push = Event {
+            kind: "push".to_string(),
+            ..event("main", "pushed", "")
+        };
+
+        assert!(rule.matches(&push));
+        assert!(!rule.matches(&event("main", "merge", "merged")));
+    }
+
+    #[test]
+    fn it_matches_tag_globs() {
+        let rule = Rule {
+            events: vec!["tag_push".to_string()],
+            tags: vec!["v*".to_string()],
+            ..Default::default()
+        };
+        let tag = |name: &str| Event {
+            kind: "tag_push".to_string(),
+            tag: name.to_string(),
+            ..Default::default()
+        };
+
+        assert!(rule.matches(&tag("v1.0.0")));
+        assert!(!rule.matches(&tag("nightly")));
+    }
+
+    #[test]
+    fn it_exports_env() {
+        let env = Event {
+            tag: "v1.0.0".to_string(),
+            ..event("", "published", "")
+        }
+        .env();
+
+        assert_eq!(env.get("SHOOK_EVENT"), Some(&"merge_request".to_string()));
+        assert_eq!(env.get("SHOOK_TAG"), Some(&"v1.0.0".to_string()));
+        assert_eq!(env.get("SHOOK_ACTION"), Some(&"published".to_string()));
+        assert_eq!(env.get("SHOOK_BRANCH"), None);
+        assert_eq!(env.get("SHOOK_STATE"), None);
+    }
+
+    #[test]
+    fn it_requires_successful_ci_events() {
+        let rules = vec![Rule {
+            events: vec!["workflow_run".to_string()],
+            branches: vec!["main".to_string()],
+            workflows: vec!["CI".to_string()],
+            ..Default::default()
+        }];
+        let run = |workflow: &str, action: &str, conclusion: &str| Event {
+            kind: "workflow_run".to_string(),
+            workflow: workflow.to_string(),
+            ..event("main", action, conclusion)
+        };
+
+        assert!(should_deploy(&rules, &run("CI", "completed", "success")));
+        assert!(!should_deploy(&rules, &run("CI", "completed", "failure")));
+        assert!(!should_deploy(&rules, &run("CI", "requested", "")));
+        assert!(!should_deploy(&rules, &run("Lint", "completed", "success")));
+    }
+
+    #[test]
+    fn it_requires_successful_pipelines() {
+        let rules = vec![Rule::new(&["pipeline"], &["main"], &[], &[])];
+        let pipeline = |status: &str| Event {
+            kind: "pipeline".to_string(),
+            ..event("main", "push", status)
+        };
+
+        assert!(should_deploy(&rules, &pipeline("success")));
+        assert!(!should_deploy(&rules, &pipeline("failed")));
+        assert!(!should_deploy(&rules, &pipeline("running")));
+    }
+
+    #[test]
+    fn it_matches_any_rule() {
+        let rules = vec![
+            Rule::new(&[], &["main"], &["merge"], &["merged"]),
+            Rule::new(&[], &["production"], &[], &[]),
+        ];
+
+        assert!(should_deploy(&rules, &event("main", "merge", "merged")));
+        assert!(should_deploy(&rules, &event("production", "open", "")));
+        assert!(!should_deploy(&rules, &event("main", "open", "merged")));
+        assert!(!should_deploy(&[], &event("main", "merge", "merged")));
+    }
+}
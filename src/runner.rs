@@ -0,0 +1,305 @@
+use async_std::channel::{self, Receiver, Sender};
+use async_std::future;
+use async_std::io::{self, prelude::BufReadExt, BufReader};
+use async_std::prelude::FutureExt;
+use async_std::process::{Child, Command, ExitStatus, Stdio};
+use futures::stream::{self, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process;
+use std::time::Duration;
+
+/// Time a process group is given to exit after `SIGTERM` before it's killed.
+const KILL_GRACE: Duration = Duration::from_secs(5);
+
+/// Output stream a line of command output was read from.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+/// How a command run ended.
+#[derive(Debug, PartialEq)]
+pub enum Outcome {
+    Exited(ExitStatus),
+    /// The timeout expired and the process group was terminated.
+    TimedOut,
+    /// The run was cancelled and the process group was terminated.
+    Cancelled,
+}
+
+/// Cancellation signal shared between a job and whoever may cancel it.
+#[derive(Clone, Debug)]
+pub struct Cancel {
+    // nothing is ever sent, closing the channel wakes everyone waiting on it
+    sender: Sender<()>,
+    receiver: Receiver<()>,
+}
+
+impl Default for Cancel {
+    fn default() -> Self {
+        let (sender, receiver) = channel::bounded(1);
+        Cancel { sender, receiver }
+    }
+}
+
+impl Cancel {
+    pub fn cancel(&self) {
+        self.sender.close();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.sender.is_closed()
+    }
+
+    /// Wait until the signal is cancelled.
+    pub async fn cancelled(&self) {
+        let _ = self.receiver.recv().await;
+    }
+}
+
+/// How waiting for the command ended.
+enum Ended {
+    /// The output was read and the command exited.
+    Finished(io::Result<ExitStatus>),
+    TimedOut,
+    Cancelled,
+}
+
+/// Send a signal to every process in the group led by `pid`.
+fn signal_group(pid: u32, signal: libc::c_int) {
+    // errors are ignored, the group is gone once every process has exited
+    unsafe {
+        libc::kill(-(pid as libc::pid_t), signal);
+    }
+}
+
+/// Terminate the process group of the child, `SIGKILL` follows `SIGTERM` once
+/// the grace period is over.
+async fn terminate(child: &mut Child) -> io::Result<ExitStatus> {
+    let pid = child.id();
+    signal_group(pid, libc::SIGTERM);
+    let status = future::timeout(KILL_GRACE, child.status()).await;
+    // processes of the group can outlive the shell, make sure they're gone too
+    signal_group(pid, libc::SIGKILL);
+    match status {
+        Ok(status) => status,
+        Err(_) => child.status().await,
+    }
+}
+
+/// Run a command with bash without blocking the executor. Output is read line
+/// by line from stdout and stderr and passed to `on_line` as it's produced.
+///
+/// The command runs in its own process group from `workdir`, or the current
+/// directory when that isn't set. When `timeout` expires or `cancel` is
+/// cancelled the whole group is terminated.
+pub async fn run<F>(
+    command: &str,
+    env: &HashMap<String, String>,
+    workdir: Option<&Path>,
+    timeout: Option<Duration>,
+    cancel: &Cancel,
+    mut on_line: F,
+) -> io::Result<Outcome>
+where
+    F: FnMut(Stream, String),
+{
+    let log = slog_scope::logger();
+    let mut shell = process::Command::new("bash");
+    shell.arg("-c").arg(command).envs(env).process_group(0);
+    if let Some(workdir) = workdir {
+        shell.current_dir(workdir);
+    }
+    let mut child = Command::from(shell)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    let stdout = BufReader::new(child.stdout.take().expect("stdout is piped"))
+        .lines()
+        .map(|line| (Stream::Stdout, line));
+    let stderr = BufReader::new(child.stderr.take().expect("stderr is piped"))
+        .lines()
+        .map(|line| (Stream::Stderr, line));
+    let mut lines = stream::select(stdout, stderr);
+
+    // the command can keep running after closing its output, so the timeout
+    // and the cancellation cover waiting for it to exit too
+    let exited = async {
+        while let Some((stream, line)) = lines.next().await {
+            match line {
+                Ok(line) => on_line(stream, line),
+                Err(e) => warn!(log, "failed to read command output"; "error" => e.to_string()),
+            }
+        }
+        child.status().await
+    };
+
+    let waiting = async {
+        match timeout {
+            None => Ended::Finished(exited.await),
+            Some(timeout) => match future::timeout(timeout, exited).await {
+                Ok(status) => Ended::Finished(status),
+                Err(_) => Ended::TimedOut,
+            },
+        }
+    };
+    let cancelled = async {
+        cancel.cancelled().await;
+        Ended::Cancelled
+    };
+
+    let ended = waiting.race(cancelled).await;
+    match ended {
+        Ended::Finished(status) => status.map(Outcome::Exited),
+        Ended::TimedOut => {
+            warn!(log, "command timed out, terminating"; "command" => command, "pid" => child.id());
+            terminate(&mut child).await?;
+            Ok(Outcome::TimedOut)
+        }
+        Ended::Cancelled => {
+            warn!(log, "command cancelled, terminating"; "command" => command, "pid" => child.id());
+            terminate(&mut child).await?;
+            Ok(Outcome::Cancelled)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_std::task;
+
+    #[test]
+    fn it_streams_output() {
+        let mut env = HashMap::new();
+        env.insert("GREETING".to_string(), "hello".to_string());
+        let mut lines = Vec::new();
+
+        let outcome = task::block_on(run(
+            "echo $GREETING; echo oops >&2; echo world",
+            &env,
+            None,
+            None,
+            &Cancel::default(),
+            |stream, line| lines.push((stream, line)),
+        ))
+        .unwrap();
+
+        match outcome {
+            Outcome::Exited(status) => assert!(status.success()),
+            _ => panic!("expected command to exit"),
+        }
+        assert_eq!(lines.len(), 3);
+        assert!(lines.contains(&(Stream::Stdout, "hello".to_string())));
+        assert!(lines.contains(&(Stream::Stdout, "world".to_string())));
+        assert!(lines.contains(&(Stream::Stderr, "oops".to_string())));
+    }
+
+    #[test]
+    fn it_returns_exit_status() {
+        let outcome = task::block_on(run(
+            "exit 3",
+            &HashMap::new(),
+            None,
+            None,
+            &Cancel::default(),
+            |_, _| {},
+        ))
+        .unwrap();
+
+        match outcome {
+            Outcome::Exited(status) => assert_eq!(status.code(), Some(3)),
+            _ => panic!("expected command to exit"),
+        }
+    }
+
+    #[test]
+    fn it_runs_in_workdir() {
+        let workdir = std::env::temp_dir().canonicalize().unwrap();
+        let mut lines = Vec::new();
+
+        task::block_on(run(
+            "pwd",
+            &HashMap::new(),
+            Some(&workdir),
+            None,
+            &Cancel::default(),
+            |_, line| lines.push(line),
+        ))
+        .unwrap();
+
+        assert_eq!(lines, vec![workdir.display().to_string()]);
+    }
+
+    #[test]
+    fn it_terminates_process_group_on_timeout() {
+        let dir = std::env::temp_dir().join(format!("shook-runner-{}", process::id()));
+        let marker = dir.join("marker");
+        std::fs::create_dir_all(&dir).unwrap();
+        let command = format!("(sleep 1 && touch {}) & sleep 5", marker.display());
+        let start = std::time::Instant::now();
+
+        let outcome = task::block_on(run(
+            &command,
+            &HashMap::new(),
+            None,
+            Some(Duration::from_millis(200)),
+            &Cancel::default(),
+            |_, _| {},
+        ))
+        .unwrap();
+
+        assert_eq!(outcome, Outcome::TimedOut);
+        assert!(start.elapsed() < Duration::from_secs(4));
+        // the background process was part of the group and never got to run
+        std::thread::sleep(Duration::from_millis(1500));
+        assert!(!marker.exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn it_times_out_commands_without_output() {
+        let start = std::time::Instant::now();
+
+        // the output is closed long before the command exits
+        let outcome = task::block_on(run(
+            "exec >&- 2>&-; sleep 5",
+            &HashMap::new(),
+            None,
+            Some(Duration::from_millis(200)),
+            &Cancel::default(),
+            |_, _| {},
+        ))
+        .unwrap();
+
+        assert_eq!(outcome, Outcome::TimedOut);
+        assert!(start.elapsed() < Duration::from_secs(4));
+    }
+
+    #[test]
+    fn it_cancels_commands() {
+        let cancel = Cancel::default();
+        let canceller = cancel.clone();
+        let start = std::time::Instant::now();
+
+        let outcome = task::block_on(async {
+            task::spawn(async move {
+                task::sleep(Duration::from_millis(200)).await;
+                canceller.cancel();
+            });
+            run("sleep 5", &HashMap::new(), None, None, &cancel, |_, _| {}).await
+        })
+        .unwrap();
+
+        assert_eq!(outcome, Outcome::Cancelled);
+        assert!(cancel.is_cancelled());
+        assert!(start.elapsed() < Duration::from_secs(4));
+    }
+}
@@ -0,0 +1,201 @@
+use serde::Deserialize;
+use std::env;
+use std::fmt;
+use std::fs;
+
+const ENV_PREFIX: &str = "${env:";
+const FILE_PREFIX: &str = "file:";
+const REDACTED: &str = "[redacted]";
+
+/// Configuration value that can reference the environment with `${env:NAME}`
+/// or a file with `file:/path`. References are resolved with `resolve`, the
+/// resolved value is never shown by `Debug` so it can't end up in the logs.
+#[derive(Clone, Default, Deserialize, PartialEq)]
+#[serde(from = "String")]
+pub struct Secret {
+    /// Value as it's written in the configuration.
+    source: String,
+    value: String,
+}
+
+impl From<String> for Secret {
+    fn from(source: String) -> Self {
+        let value = match Self::is_reference_of(&source) {
+            true => String::new(),
+            false => source.clone(),
+        };
+        Secret { source, value }
+    }
+}
+
+impl From<&str> for Secret {
+    fn from(source: &str) -> Self {
+        Self::from(source.to_string())
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.is_reference() {
+            true => write!(f, "Secret({:?})", self.source),
+            false => write!(f, "Secret({})", REDACTED),
+        }
+    }
+}
+
+impl Secret {
+    fn is_reference_of(source: &str) -> bool {
+        source.starts_with(FILE_PREFIX) || source.contains(ENV_PREFIX)
+    }
+
+    /// Whether the value comes from the environment or a file.
+    pub fn is_reference(&self) -> bool {
+        Self::is_reference_of(&self.source)
+    }
+
+    /// The resolved value.
+    pub fn expose(&self) -> &str {
+        &self.value
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.value.trim().is_empty()
+    }
+
+    /// Read the referenced value, plain values are kept as they are. A file is
+    /// read without its trailing newline, and every `${env:NAME}` of a value
+    /// is replaced.
+    pub fn resolve(&mut self) -> Result<(), String> {
+        if let Some(path) = self.source.strip_prefix(FILE_PREFIX) {
+            let value = fs::read_to_string(path)
+                .map_err(|e| format!("failed to read secret file {}: {}", path, e))?;
+            self.value = value.trim_end_matches(['\r', '\n']).to_string();
+            return Ok(());
+        }
+
+        self.value = self.interpolate(|name| {
+            env::var(name)
+                .map_err(|e| format!("environment variable {} can't be read: {}", name, e))
+        })?;
+        Ok(())
+    }
+
+    /// Check the syntax of the references without reading them, for checking a
+    /// configuration where the secrets aren't available. The reference is kept
+    /// as the value so it isn't taken for an empty one.
+    pub fn check(&mut self) -> Result<(), String> {
+        match self.source.strip_prefix(FILE_PREFIX) {
+            Some(path) if path.trim().is_empty() => {
+                return Err("secret file path is empty".to_string())
+            }
+            Some(_) => (),
+            None => {
+                self.interpolate(|name| match name.is_empty() || name.contains('=') {
+                    true => Err(format!("invalid environment variable name `{}`", name)),
+                    false => Ok(String::new()),
+                })?;
+            }
+        }
+        self.value = self.source.clone();
+        Ok(())
+    }
+
+    /// Replace every `${env:NAME}` of the source with what `lookup` returns
+    /// for the name.
+    fn interpolate<F>(&self, lookup: F) -> Result<String, String>
+    where
+        F: Fn(&str) -> Result<String, String>,
+    {
+        let mut value = String::new();
+        let mut rest = self.source.as_str();
+        while let Some(start) = rest.find(ENV_PREFIX) {
+            value.push_str(&rest[..start]);
+            let reference = &rest[start + ENV_PREFIX.len()..];
+            let end = reference
+                .find('}')
+                .ok_or_else(|| format!("unterminated reference in `{}`", self.source))?;
+            value.push_str(&lookup(&reference[..end])?);
+            rest = &reference[end + 1..];
+        }
+        value.push_str(rest);
+        Ok(value)
+    }
+}
+
+/// Replace every resolved secret in `text`.
+pub fn redact(text: &str, secrets: &[String]) -> String {
+    secrets
+        .iter()
+        .filter(|secret| !secret.is_empty())
+        .fold(text.to_string(), |text, secret| {
+            text.replace(secret.as_str(), REDACTED)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_keeps_plain_values() {
+        let mut secret = Secret::from("plain");
+        secret.resolve().unwrap();
+        assert!(!secret.is_reference());
+        assert_eq!(secret.expose(), "plain");
+        assert_eq!(format!("{:?}", secret), "Secret([redacted])");
+    }
+
+    #[test]
+    fn it_resolves_environment_references() {
+        env::set_var("SHOOK_SECRET_TEST", "from-env");
+        let mut secret = Secret::from("token-${env:SHOOK_SECRET_TEST}");
+        assert_eq!(secret.expose(), "");
+
+        secret.resolve().unwrap();
+        assert_eq!(secret.expose(), "token-from-env");
+        assert_eq!(
+            format!("{:?}", secret),
+            "Secret(\"token-${env:SHOOK_SECRET_TEST}\")"
+        );
+
+        let mut missing = Secret::from("${env:SHOOK_SECRET_MISSING}");
+        assert!(missing.resolve().is_err());
+        let mut unterminated = Secret::from("${env:SHOOK_SECRET_TEST");
+        assert!(unterminated.resolve().is_err());
+    }
+
+    #[test]
+    fn it_checks_references_without_reading_them() {
+        let mut secret = Secret::from("${env:SHOOK_SECRET_NOT_SET}");
+        secret.check().unwrap();
+        assert!(!secret.is_empty());
+        let mut secret = Secret::from("file:/nonexistent/shook/secret");
+        secret.check().unwrap();
+
+        assert!(Secret::from("${env:SHOOK_SECRET_TEST").check().is_err());
+        assert!(Secret::from("token-${env:}").check().is_err());
+        assert!(Secret::from("file:").check().is_err());
+    }
+
+    #[test]
+    fn it_resolves_file_references() {
+        let path = env::temp_dir().join(format!("shook-secret-{}", std::process::id()));
+        fs::write(&path, "from-file\n").unwrap();
+
+        let mut secret = Secret::from(format!("file:{}", path.display()));
+        secret.resolve().unwrap();
+        assert_eq!(secret.expose(), "from-file");
+
+        fs::remove_file(&path).unwrap();
+        assert!(secret.resolve().is_err());
+    }
+
+    #[test]
+    fn it_redacts_secrets() {
+        let secrets = vec!["hunter2".to_string(), String::new()];
+        assert_eq!(
+            redact("password is hunter2", &secrets),
+            "password is [redacted]"
+        );
+    }
+}
@@ -0,0 +1,355 @@
+use chrono::prelude::*;
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use std::path::Path;
+use std::sync::Mutex;
+
+use crate::job::{Job, Status};
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS jobs (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        project TEXT NOT NULL,
+        provider TEXT NOT NULL,
+        event TEXT NOT NULL,
+        sha TEXT NOT NULL,
+        trigger TEXT NOT NULL,
+        status TEXT NOT NULL,
+        failed_step INTEGER,
+        queued_at TEXT NOT NULL,
+        started_at TEXT,
+        finished_at TEXT,
+        steps TEXT NOT NULL DEFAULT '[]'
+    );
+    CREATE INDEX IF NOT EXISTS jobs_project ON jobs (project);
+";
+
+const COLUMNS: &str = "id, project, provider, event, sha, trigger, status, failed_step, \
+                       queued_at, started_at, finished_at";
+
+/// Job history kept in a SQLite database.
+pub struct Store {
+    connection: Mutex<Connection>,
+}
+
+fn status_to_sql(status: Status) -> String {
+    match serde_json::to_value(status) {
+        Ok(serde_json::Value::String(status)) => status,
+        _ => "undefined".to_string(),
+    }
+}
+
+fn status_from_sql(status: String) -> rusqlite::Result<Status> {
+    serde_json::from_value(serde_json::Value::String(status)).map_err(|e| {
+        rusqlite::Error::FromSqlConversionFailure(6, rusqlite::types::Type::Text, e.into())
+    })
+}
+
+/// Read a job from a row selected with `COLUMNS`, the steps are left empty.
+fn job_from_row(row: &Row) -> rusqlite::Result<Job> {
+    Ok(Job {
+        id: Some(row.get(0)?),
+        project: row.get(1)?,
+        provider: row.get(2)?,
+        event: row.get(3)?,
+        sha: row.get(4)?,
+        trigger: row.get(5)?,
+        status: status_from_sql(row.get(6)?)?,
+        failed_step: row.get::<_, Option<i64>>(7)?.map(|step| step as usize),
+        queued_at: row.get::<_, DateTime<Utc>>(8)?,
+        started_at: row.get::<_, Option<DateTime<Utc>>>(9)?,
+        finished_at: row.get::<_, Option<DateTime<Utc>>>(10)?,
+        steps: Vec::new(),
+    })
+}
+
+impl Store {
+    /// Open the database at `path`, it's created when it doesn't exist.
+    pub fn open<P: AsRef<Path>>(path: P) -> rusqlite::Result<Self> {
+        Self::setup(Connection::open(path)?)
+    }
+
+    #[cfg(test)]
+    pub fn open_in_memory() -> rusqlite::Result<Self> {
+        Self::setup(Connection::open_in_memory()?)
+    }
+
+    fn setup(connection: Connection) -> rusqlite::Result<Self> {
+        connection.execute_batch(SCHEMA)?;
+        Ok(Store {
+            connection: Mutex::new(connection),
+        })
+    }
+
+    /// Add the job to the history and return its id.
+    pub fn insert(&self, job: &Job) -> rusqlite::Result<i64> {
+        let connection = self.connection.lock().unwrap();
+        connection.execute(
+            "INSERT INTO jobs (project, provider, event, sha, trigger, status, queued_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                job.project,
+                job.provider,
+                job.event,
+                job.sha,
+                job.trigger,
+                status_to_sql(job.status),
+                job.queued_at,
+            ],
+        )?;
+        Ok(connection.last_insert_rowid())
+    }
+
+    /// Save the commit, status and steps of a job that was inserted before.
+    pub fn update(&self, id: i64, job: &Job) -> rusqlite::Result<()> {
+        let steps = serde_json::to_string(&job.steps)
+            .map_err(|e| rusqlite::Error::ToSqlConversionFailure(e.into()))?;
+        self.connection.lock().unwrap().execute(
+            "UPDATE jobs SET sha = ?1, status = ?2, failed_step = ?3, started_at = ?4,
+             finished_at = ?5, steps = ?6 WHERE id = ?7",
+            params![
+                job.sha,
+                status_to_sql(job.status),
+                job.failed_step.map(|step| step as i64),
+                job.started_at,
+                job.finished_at,
+                steps,
+                id,
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Mark a job that's still queued as cancelled.
+    pub fn cancel(&self, id: i64) -> rusqlite::Result<()> {
+        self.connection.lock().unwrap().execute(
+            "UPDATE jobs SET status = ?1, finished_at = ?2 WHERE id = ?3 AND status = ?4",
+            params![
+                status_to_sql(Status::Cancelled),
+                Utc::now(),
+                id,
+                status_to_sql(Status::Queued),
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Finish the jobs a previous run of shook left unfinished, queued jobs
+    /// are cancelled and running ones failed. Returns the number of jobs.
+    pub fn interrupt(&self) -> rusqlite::Result<usize> {
+        let connection = self.connection.lock().unwrap();
+        let finish = "UPDATE jobs SET status = ?1, finished_at = ?2 WHERE status = ?3";
+        let now = Utc::now();
+        let cancelled = connection.execute(
+            finish,
+            params![
+                status_to_sql(Status::Cancelled),
+                now,
+                status_to_sql(Status::Queued),
+            ],
+        )?;
+        let failed = connection.execute(
+            finish,
+            params![
+                status_to_sql(Status::Failed),
+                now,
+                status_to_sql(Status::Running),
+            ],
+        )?;
+        Ok(cancelled + failed)
+    }
+
+    /// Remove a job that never ran from the history.
+    pub fn remove(&self, id: i64) -> rusqlite::Result<()> {
+        self.connection
+            .lock()
+            .unwrap()
+            .execute("DELETE FROM jobs WHERE id = ?1", params![id])?;
+        Ok(())
+    }
+
+    /// Status of the job with the given id.
+    pub fn status(&self, id: i64) -> rusqlite::Result<Option<Status>> {
+        let connection = self.connection.lock().unwrap();
+        let status = connection
+            .query_row(
+                "SELECT status FROM jobs WHERE id = ?1",
+                params![id],
+                |row| row.get::<_, String>(0),
+            )
+            .optional()?;
+        status.map(status_from_sql).transpose()
+    }
+
+    /// Name of the project of the job with the given id.
+    pub fn project(&self, id: i64) -> rusqlite::Result<Option<String>> {
+        let connection = self.connection.lock().unwrap();
+        connection
+            .query_row(
+                "SELECT project FROM jobs WHERE id = ?1",
+                params![id],
+                |row| row.get(0),
+            )
+            .optional()
+    }
+
+    /// Job with the given id including the output of its steps.
+    pub fn get(&self, id: i64) -> rusqlite::Result<Option<Job>> {
+        let connection = self.connection.lock().unwrap();
+        connection
+            .query_row(
+                &format!("SELECT {}, steps FROM jobs WHERE id = ?1", COLUMNS),
+                params![id],
+                |row| {
+                    let mut job = job_from_row(row)?;
+                    let steps: String = row.get(11)?;
+                    job.steps = serde_json::from_str(&steps).map_err(|e| {
+                        rusqlite::Error::FromSqlConversionFailure(
+                            11,
+                            rusqlite::types::Type::Text,
+                            e.into(),
+                        )
+                    })?;
+                    Ok(job)
+                },
+            )
+            .optional()
+    }
+
+    /// Newest jobs first, optionally only those of one project. The steps of
+    /// the jobs aren't loaded.
+    pub fn list(&self, project: Option<&str>, limit: usize) -> rusqlite::Result<Vec<Job>> {
+        let connection = self.connection.lock().unwrap();
+        let mut statement = connection.prepare(&format!(
+            "SELECT {} FROM jobs WHERE ?1 IS NULL OR project = ?1 ORDER BY id DESC LIMIT ?2",
+            COLUMNS
+        ))?;
+        let jobs = statement
+            .query_map(params![project, limit as i64], job_from_row)?
+            .collect();
+        jobs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runner::Stream;
+
+    fn job(project: &str) -> Job {
+        let mut job = Job::new(project);
+        job.provider = "github".to_string();
+        job.event = "push".to_string();
+        job.sha = "abc123".to_string();
+        job.trigger = "webhook".to_string();
+        job
+    }
+
+    #[test]
+    fn it_stores_jobs() {
+        let store = Store::open_in_memory().unwrap();
+        let mut job = job("sample");
+        let id = store.insert(&job).unwrap();
+
+        let stored = store.get(id).unwrap().unwrap();
+        assert_eq!(store.project(id).unwrap(), Some("sample".to_string()));
+        assert_eq!(stored.id, Some(id));
+        assert_eq!(stored.status, Status::Queued);
+        assert_eq!(stored.sha, "abc123".to_string());
+        assert_eq!(stored.started_at, None);
+        assert!(stored.steps.is_empty());
+
+        job.start();
+        job.sha = "def456".to_string();
+        store.update(id, &job).unwrap();
+        assert_eq!(store.status(id).unwrap(), Some(Status::Running));
+        assert_eq!(store.get(id).unwrap().unwrap().sha, "def456".to_string());
+
+        job.start_step("false");
+        job.push_line(Stream::Stderr, "failed".to_string());
+        job.finish_step(Some(1));
+        job.fail(0);
+        store.update(id, &job).unwrap();
+
+        let stored = store.get(id).unwrap().unwrap();
+        assert_eq!(stored.status, Status::Failed);
+        assert_eq!(stored.failed_step, Some(0));
+        assert_eq!(stored.queued_at, job.queued_at);
+        assert_eq!(stored.started_at, job.started_at);
+        assert_eq!(stored.finished_at, job.finished_at);
+        assert_eq!(stored.steps, job.steps);
+    }
+
+    #[test]
+    fn it_returns_none_for_unknown_jobs() {
+        let store = Store::open_in_memory().unwrap();
+        assert_eq!(store.get(42).unwrap(), None);
+        assert_eq!(store.status(42).unwrap(), None);
+        assert_eq!(store.project(42).unwrap(), None);
+    }
+
+    #[test]
+    fn it_cancels_queued_jobs() {
+        let store = Store::open_in_memory().unwrap();
+        let queued = store.insert(&job("sample")).unwrap();
+        let mut running = job("sample");
+        running.start();
+        let running = store.insert(&running).unwrap();
+
+        store.cancel(queued).unwrap();
+        store.cancel(running).unwrap();
+
+        let cancelled = store.get(queued).unwrap().unwrap();
+        assert_eq!(cancelled.status, Status::Cancelled);
+        assert!(cancelled.finished_at.is_some());
+        assert_eq!(store.status(running).unwrap(), Some(Status::Running));
+    }
+
+    #[test]
+    fn it_interrupts_unfinished_jobs() {
+        let store = Store::open_in_memory().unwrap();
+        let queued = store.insert(&job("sample")).unwrap();
+        let mut running = job("sample");
+        running.start();
+        let running_id = store.insert(&running).unwrap();
+        store.update(running_id, &running).unwrap();
+        let mut finished = job("sample");
+        finished.start();
+        finished.succeed();
+        let finished_id = store.insert(&finished).unwrap();
+        store.update(finished_id, &finished).unwrap();
+
+        assert_eq!(store.interrupt().unwrap(), 2);
+        let cancelled = store.get(queued).unwrap().unwrap();
+        assert_eq!(cancelled.status, Status::Cancelled);
+        assert!(cancelled.finished_at.is_some());
+        let failed = store.get(running_id).unwrap().unwrap();
+        assert_eq!(failed.status, Status::Failed);
+        assert!(failed.finished_at.is_some());
+        assert_eq!(store.status(finished_id).unwrap(), Some(Status::Succeeded));
+        assert_eq!(store.interrupt().unwrap(), 0);
+    }
+
+    #[test]
+    fn it_removes_jobs() {
+        let store = Store::open_in_memory().unwrap();
+        let id = store.insert(&job("sample")).unwrap();
+        store.remove(id).unwrap();
+        assert_eq!(store.get(id).unwrap(), None);
+    }
+
+    #[test]
+    fn it_lists_newest_jobs_first() {
+        let store = Store::open_in_memory().unwrap();
+        let first = store.insert(&job("sample")).unwrap();
+        let other = store.insert(&job("other")).unwrap();
+        let last = store.insert(&job("sample")).unwrap();
+
+        let ids = |jobs: Vec<Job>| jobs.iter().map(|job| job.id.unwrap()).collect::<Vec<i64>>();
+        assert_eq!(ids(store.list(None, 10).unwrap()), vec![last, other, first]);
+        assert_eq!(
+            ids(store.list(Some("sample"), 10).unwrap()),
+            vec![last, first]
+        );
+        assert_eq!(ids(store.list(None, 1).unwrap()), vec![last]);
+    }
+}
@@ -1,9 +1,10 @@
 use hmac::{Hmac, Mac};
 use serde::Deserialize;
 use sha2::Sha256;
-use std::env;
-use std::io;
-use std::path::Path;
+use std::collections::HashMap;
+
+use crate::git::{self, Ref, Source};
+use crate::rules;
 
 type HmacSha256 = Hmac<Sha256>;
 
@@ -40,10 +41,60 @@ struct PullRequest {
     title: Option<String>,
     merged: Option<bool>,
     merged_at: Option<String>,
+    merge_commit_sha: Option<String>,
     head: PullRequestHead,
     base: PullRequestBase,
 }
 
+#[derive(Deserialize)]
+struct Pusher {
+    name: Option<String>,
+    email: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Commit {
+    id: Option<String>,
+    message: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Release {
+    tag_name: Option<String>,
+    name: Option<String>,
+    target_commitish: Option<String>,
+    html_url: Option<String>,
+    #[serde(default)]
+    draft: bool,
+    #[serde(default)]
+    prerelease: bool,
+}
+
+#[derive(Deserialize)]
+struct WorkflowRun {
+    name: Option<String>,
+    head_branch: Option<String>,
+    head_sha: Option<String>,
+    status: Option<String>,
+    conclusion: Option<String>,
+    event: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct App {
+    name: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct CheckSuite {
+    head_branch: Option<String>,
+    head_sha: Option<String>,
+    status: Option<String>,
+    conclusion: Option<String>,
+    app: Option<App>,
+}
+
+/// Payload of a `pull_request` event.
 #[derive(Deserialize)]
 pub struct Webhook {
     action: Option<String>,
@@ -52,47 +103,158 @@ pub struct Webhook {
     sender: Option<User>,
 }
 
-impl Webhook {
-    pub fn action(&self) -> String {
-        match &self.action {
+/// Payload of a `push` event.
+#[derive(Deserialize)]
+pub struct Push {
+    r#ref: Option<String>,
+    before: Option<String>,
+    after: Option<String>,
+    #[serde(default)]
+    created: bool,
+    #[serde(default)]
+    deleted: bool,
+    #[serde(default)]
+    commits: Vec<Commit>,
+    repository: Repository,
+    pusher: Option<Pusher>,
+    sender: Option<User>,
+}
+
+/// Payload of a `release` event.
+#[derive(Deserialize)]
+pub struct ReleaseEvent {
+    action: Option<String>,
+    release: Release,
+    repository: Repository,
+    sender: Option<User>,
+}
+
+/// Payload of a `create` event, sent when a branch or tag is created.
+#[derive(Deserialize)]
+pub struct Create {
+    r#ref: Option<String>,
+    ref_type: Option<String>,
+    repository: Repository,
+    sender: Option<User>,
+}
+
+/// Payload of a `workflow_run` event.
+#[derive(Deserialize)]
+pub struct WorkflowRunEvent {
+    action: Option<String>,
+    workflow_run: WorkflowRun,
+    repository: Repository,
+    sender: Option<User>,
+}
+
+/// Payload of a `check_suite` event.
+#[derive(Deserialize)]
+pub struct CheckSuiteEvent {
+    action: Option<String>,
+    check_suite: CheckSuite,
+    repository: Repository,
+    sender: Option<User>,
+}
+
+/// Supported GitHub events, selected by the `X-GitHub-Event` header.
+pub enum Event {
+    PullRequest(Webhook),
+    Push(Push),
+    Release(ReleaseEvent),
+    Create(Create),
+    WorkflowRun(WorkflowRunEvent),
+    CheckSuite(CheckSuiteEvent),
+}
+
+fn value_or_undefined(value: &Option<String>) -> String {
+    match value {
+        None => "undefined".to_string(),
+        Some(value) => value.clone(),
+    }
+}
+
+impl User {
+    /// Login of the user, `undefined` without a user or login.
+    fn login_of(user: &Option<User>) -> String {
+        match user {
             None => "undefined".to_string(),
-            Some(value) => value.clone(),
+            Some(user) => value_or_undefined(&user.login),
         }
     }
+}
 
-    pub fn repository_name(&self) -> String {
-        match &self.repository.name {
-            None => "undefined".to_string(),
+impl Repository {
+    fn name(&self) -> String {
+        value_or_undefined(&self.name)
+    }
+
+    fn full_name(&self) -> String {
+        value_or_undefined(&self.full_name)
+    }
+
+    fn default_branch(&self) -> String {
+        match &self.default_branch {
+            None => "main".to_string(), // GitHub default
             Some(value) => value.clone(),
         }
     }
 
-    pub fn repository_full_name(&self) -> String {
-        match &self.repository.full_name {
-            None => "undefined".to_string(),
-            Some(value) => value.clone(),
+    fn clone_url(&self) -> String {
+        value_or_undefined(&self.clone_url)
+    }
+
+    fn ssh_url(&self) -> String {
+        value_or_undefined(&self.ssh_url)
+    }
+
+    /// Source of the repository at `reference`, the checkout is kept under the
+    /// full name of the repository.
+    fn git_source(&self, reference: Ref) -> Source {
+        Source {
+            url: self.clone_url(),
+            ssh_url: self.ssh_url.clone(),
+            path: self.full_name(),
+            reference,
+            sha: None,
         }
     }
 
+    fn dump(&self) {
+        let log = slog_scope::logger();
+
+        debug!(log, "github webhook repository";
+            "name" => self.name(),
+            "full_name" => self.full_name(),
+            "clone_url" => self.clone_url(),
+            "ssh_url" => self.ssh_url(),
+            "default_branch" => self.default_branch(),
+        );
+    }
+}
+
+impl Webhook {
+    pub fn action(&self) -> String {
+        value_or_undefined(&self.action)
+    }
+
+    pub fn repository_name(&self) -> String {
+        self.repository.name()
+    }
+
+    pub fn repository_full_name(&self) -> String {
+        self.repository.full_name()
+    }
+
     pub fn default_branch(&self) -> String {
-        match &self.repository.default_branch {
-            None => "main".to_string(),  // GitHub default
-            Some(value) => value.clone(),
-        }
+        self.repository.default_branch()
     }
 
     pub fn clone_url(&self) -> String {
-        match &self.repository.clone_url {
-            None => "undefined".to_string(),
-            Some(value) => value.clone(),
-        }
+        self.repository.clone_url()
     }
 
     pub fn ssh_url(&self) -> String {
-        match &self.repository.ssh_url {
-            None => "undefined".to_string(),
-            Some(value) => value.clone(),
-        }
+        self.repository.ssh_url()
     }
 
     pub fn is_merged(&self) -> bool {
@@ -132,97 +294,95 @@ impl Webhook {
         }
     }
 
-    pub fn pr_number(&self) -> u64 {
+    pub fn head_sha(&self) -> String {
         match &self.pull_request {
-            None => 0,
-            Some(pr) => pr.number.unwrap_or(0),
+            None => "undefined".to_string(),
+            Some(pr) => match &pr.head.sha {
+                None => "undefined".to_string(),
+                Some(value) => value.clone(),
+            },
         }
     }
 
-    pub fn pr_title(&self) -> String {
+    pub fn base_sha(&self) -> String {
         match &self.pull_request {
             None => "undefined".to_string(),
-            Some(pr) => match &pr.title {
+            Some(pr) => match &pr.base.sha {
                 None => "undefined".to_string(),
                 Some(value) => value.clone(),
             },
         }
     }
 
-    pub fn sender(&self) -> String {
-        match &self.sender {
+    pub fn merge_commit_sha(&self) -> String {
+        match &self.pull_request {
             None => "undefined".to_string(),
-            Some(user) => match &user.login {
+            Some(pr) => value_or_undefined(&pr.merge_commit_sha),
+        }
+    }
+
+    pub fn merged_at(&self) -> String {
+        match &self.pull_request {
+            None => "undefined".to_string(),
+            Some(pr) => match &pr.merged_at {
                 None => "undefined".to_string(),
                 Some(value) => value.clone(),
             },
         }
     }
 
-    fn fast_forward(&self, path: &Path) -> Result<(), git2::Error> {
-        let repo = git2::Repository::open(path)?;
-
-        repo.find_remote("origin")?
-            .fetch(&[self.default_branch()], None, None)?;
+    pub fn pr_number(&self) -> u64 {
+        match &self.pull_request {
+            None => 0,
+            Some(pr) => pr.number.unwrap_or(0),
+        }
+    }
 
-        let fetch_head = repo.find_reference("FETCH_HEAD")?;
-        let fetch_commit = repo.reference_to_annotated_commit(&fetch_head)?;
-        let analysis = repo.merge_analysis(&[&fetch_commit])?;
+    pub fn pr_title(&self) -> String {
+        match &self.pull_request {
+            None => "undefined".to_string(),
+            Some(pr) => match &pr.title {
+                None => "undefined".to_string(),
+                Some(value) => value.clone(),
+            },
+        }
+    }
 
-        if analysis.0.is_up_to_date() {
-            Ok(())
-        } else if analysis.0.is_fast_forward() {
-            let refname = format!("refs/heads/{}", self.default_branch());
-            let mut reference = repo.find_reference(&refname)?;
+    pub fn sender(&self) -> String {
+        User::login_of(&self.sender)
+    }
 
-            reference.set_target(fetch_commit.id(), "Fast-Forward")?;
-            repo.set_head(&refname)?;
-            repo.checkout_head(Some(git2::build::CheckoutBuilder::default().force()))
-        } else {
-            Err(git2::Error::from_str("Fast-forward only!"))
+    /// Normalized event for deploy rule evaluation, a merged pull request has
+    /// the state `merged`.
+    pub fn event(&self) -> rules::Event {
+        rules::Event {
+            kind: "pull_request".to_string(),
+            branch: self.target_branch(),
+            action: self.action(),
+            state: match self.is_merged() {
+                true => "merged".to_string(),
+                false => self.pr_state(),
+            },
+            ..Default::default()
         }
     }
 
-    fn reset(&self, path: &Path) {
-        let repo = match git2::Repository::open(path) {
-            Ok(repo) => repo,
-            Err(e) => panic!("Failed to open: {}", e),
-        };
-        repo.reset(
-            &repo.revparse_single("HEAD").unwrap(),
-            git2::ResetType::Hard,
-            None,
-        )
-        .unwrap();
-    }
-
-    pub fn clone_repository(&self) -> Result<String, io::Error> {
-        let project = self.repository_name();
-        let base = "/var/cache/shook/".to_string();
-        let path = format!("{}{}", base, project);
-        let repo_path = Path::new(&path);
-
-        match repo_path.exists() && repo_path.is_dir() {
-            true => {
-                let root = Path::new(&path);
-                env::set_current_dir(&root)?;
-                self.reset(repo_path);
-                if let Err(e) = self.fast_forward(repo_path) {
-                    panic!("Failed to pull: {}", e)
-                }
-            }
-            false => {
-                let root = Path::new(&base);
-                env::set_current_dir(&root)?;
-                // Use HTTPS URL for GitHub (more compatible than SSH in most cases)
-                let _ = match git2::Repository::clone(&self.clone_url(), &project) {
-                    Ok(repo) => repo,
-                    Err(e) => panic!("failed to clone repository: {}", e),
-                };
-            }
+    /// Source of the target branch of the pull request at the merge commit
+    /// when it was merged, at the base commit otherwise.
+    pub fn git_source(&self) -> Source {
+        let sha = self
+            .pull_request
+            .as_ref()
+            .and_then(|pr| match self.is_merged() {
+                true => pr.merge_commit_sha.clone().or_else(|| pr.base.sha.clone()),
+                false => pr.base.sha.clone(),
+            });
+        Source {
+            sha,
+            ..self
+                .repository
+                .git_source(Ref::Branch(self.target_branch()))
         }
-
-        Ok(format!("/var/cache/shook/{}", project))
     }
 
     pub fn dump(&self) {
@@ -242,8 +402,12 @@ impl Webhook {
                 "number" => self.pr_number(),
                 "state" => self.pr_state(),
                 "merged" => self.is_merged(),
+                "merged_at" => self.merged_at(),
+                "merge_commit_sha" => self.merge_commit_sha(),
                 "target_branch" => self.target_branch(),
                 "source_branch" => self.source_branch(),
+                "head_sha" => self.head_sha(),
+                "base_sha" => self.base_sha(),
                 "title" => self.pr_title(),
             );
         }
@@ -252,6 +416,492 @@ impl Webhook {
     }
 }
 
+impl Push {
+    /// Push of `branch`, or of no branch, for a manual deployment of the repository at the clone
+    /// URL, its full name is the path of the URL.
+    pub fn manual(repository: &str, branch: Option<&str>, sha: Option<&str>) -> Self {
+        let path = git::repository_path(repository).unwrap_or_default();
+        Push {
+            r#ref: branch.map(|branch| format!("refs/heads/{}", branch)),
+            before: None,
+            after: sha.map(str::to_string),
+            created: false,
+            deleted: false,
+            commits: Vec::new(),
+            repository: Repository {
+                name: path.split('/').next_back().map(str::to_string),
+                full_name: Some(path.clone()),
+                clone_url: Some(repository.to_string()),
+                ssh_url: None,
+                default_branch: branch.map(str::to_string),
+            },
+            pusher: None,
+            sender: None,
+        }
+    }
+
+    pub fn r#ref(&self) -> String {
+        value_or_undefined(&self.r#ref)
+    }
+
+    /// Branch name of the pushed ref, empty for tag pushes.
+    pub fn branch(&self) -> String {
+        match self.r#ref().strip_prefix("refs/heads/") {
+            None => String::new(),
+            Some(branch) => branch.to_string(),
+        }
+    }
+
+    /// Tag name of the pushed ref, empty for branch pushes.
+    pub fn tag(&self) -> String {
+        match self.r#ref().strip_prefix("refs/tags/") {
+            None => String::new(),
+            Some(tag) => tag.to_string(),
+        }
+    }
+
+    pub fn before(&self) -> String {
+        value_or_undefined(&self.before)
+    }
+
+    pub fn after(&self) -> String {
+        value_or_undefined(&self.after)
+    }
+
+    /// Either `created`, `deleted` or `pushed` for updates of an existing ref.
+    pub fn action(&self) -> String {
+        match (self.created, self.deleted) {
+            (_, true) => "deleted".to_string(),
+            (true, _) => "created".to_string(),
+            _ => "pushed".to_string(),
+        }
+    }
+
+    pub fn commit_ids(&self) -> Vec<String> {
+        self.commits
+            .iter()
+            .map(|commit| value_or_undefined(&commit.id))
+            .collect()
+    }
+
+    pub fn head_commit_message(&self) -> String {
+        match self.commits.last() {
+            None => "undefined".to_string(),
+            Some(commit) => value_or_undefined(&commit.message),
+        }
+    }
+
+    pub fn pusher(&self) -> String {
+        match &self.pusher {
+            None => "undefined".to_string(),
+            Some(pusher) => value_or_undefined(&pusher.name),
+        }
+    }
+
+    pub fn pusher_email(&self) -> String {
+        match &self.pusher {
+            None => "undefined".to_string(),
+            Some(pusher) => value_or_undefined(&pusher.email),
+        }
+    }
+
+    pub fn sender(&self) -> String {
+        User::login_of(&self.sender)
+    }
+
+    /// Normalized event for deploy rule evaluation.
+    pub fn event(&self) -> rules::Event {
+        rules::Event {
+            kind: "push".to_string(),
+            branch: self.branch(),
+            tag: self.tag(),
+            action: self.action(),
+            ..Default::default()
+        }
+    }
+
+    /// Source of the pushed branch or tag at the pushed commit, the default
+    /// branch for refs that are neither.
+    pub fn git_source(&self) -> Source {
+        let reference = match (self.branch(), self.tag()) {
+            (branch, _) if !branch.is_empty() => Ref::Branch(branch),
+            (_, tag) if !tag.is_empty() => Ref::Tag(tag),
+            _ => Ref::Branch(self.repository.default_branch()),
+        };
+        Source {
+            // deleted refs don't point to a commit anymore
+            sha: self.after.clone().filter(|_| !self.deleted),
+            ..self.repository.git_source(reference)
+        }
+    }
+
+    pub fn dump(&self) {
+        let log = slog_scope::logger();
+
+        debug!(log, "github webhook event"; "action" => self.action());
+        self.repository.dump();
+        debug!(log, "github webhook push";
+            "ref" => self.r#ref(),
+            "before" => self.before(),
+            "after" => self.after(),
+            "commits" => self.commit_ids().join(","),
+            "head_commit_message" => self.head_commit_message(),
+            "pusher" => self.pusher(),
+            "pusher_email" => self.pusher_email(),
+        );
+        debug!(log, "github webhook sender"; "login" => self.sender());
+    }
+}
+
+impl ReleaseEvent {
+    pub fn action(&self) -> String {
+        value_or_undefined(&self.action)
+    }
+
+    pub fn tag_name(&self) -> String {
+        value_or_undefined(&self.release.tag_name)
+    }
+
+    pub fn name(&self) -> String {
+        value_or_undefined(&self.release.name)
+    }
+
+    pub fn target_commitish(&self) -> String {
+        value_or_undefined(&self.release.target_commitish)
+    }
+
+    pub fn html_url(&self) -> String {
+        value_or_undefined(&self.release.html_url)
+    }
+
+    pub fn is_draft(&self) -> bool {
+        self.release.draft
+    }
+
+    pub fn is_prerelease(&self) -> bool {
+        self.release.prerelease
+    }
+
+    pub fn sender(&self) -> String {
+        User::login_of(&self.sender)
+    }
+
+    /// Normalized event for deploy rule evaluation, the state is `prerelease`
+    /// or `release`.
+    pub fn event(&self) -> rules::Event {
+        let state = match self.is_prerelease() {
+            true => "prerelease",
+            false => "release",
+        };
+        rules::Event {
+            kind: "release".to_string(),
+            tag: self.tag_name(),
+            action: self.action(),
+            state: state.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Variables of the event, the release values the payload doesn't have are
+    /// left out.
+    pub fn env(&self) -> HashMap<String, String> {
+        let mut env = self.event().env();
+        let release = &self.release;
+        let values = [
+            ("SHOOK_RELEASE_NAME", &release.name),
+            ("SHOOK_RELEASE_URL", &release.html_url),
+            ("SHOOK_RELEASE_TARGET", &release.target_commitish),
+        ];
+        for (name, value) in values {
+            if let Some(value) = value {
+                env.insert(name.to_string(), value.clone());
+            }
+        }
+        env.insert(
+            "SHOOK_RELEASE_PRERELEASE".to_string(),
+            self.is_prerelease().to_string(),
+        );
+        env
+    }
+
+    pub fn git_source(&self) -> Source {
+        self.repository.git_source(Ref::Tag(self.tag_name()))
+    }
+
+    pub fn dump(&self) {
+        let log = slog_scope::logger();
+
+        debug!(log, "github webhook event"; "action" => self.action());
+        self.repository.dump();
+        debug!(log, "github webhook release";
+            "tag_name" => self.tag_name(),
+            "name" => self.name(),
+            "target_commitish" => self.target_commitish(),
+            "html_url" => self.html_url(),
+            "draft" => self.is_draft(),
+            "prerelease" => self.is_prerelease(),
+        );
+        debug!(log, "github webhook sender"; "login" => self.sender());
+    }
+}
+
+impl Create {
+    pub fn r#ref(&self) -> String {
+        value_or_undefined(&self.r#ref)
+    }
+
+    /// Either `branch` or `tag`.
+    pub fn ref_type(&self) -> String {
+        value_or_undefined(&self.ref_type)
+    }
+
+    pub fn is_tag(&self) -> bool {
+        self.ref_type() == "tag"
+    }
+
+    pub fn sender(&self) -> String {
+        User::login_of(&self.sender)
+    }
+
+    /// Normalized event for deploy rule evaluation, the ref type is used as the
+    /// action.
+    pub fn event(&self) -> rules::Event {
+        let (branch, tag) = match self.is_tag() {
+            true => (String::new(), self.r#ref()),
+            false => (self.r#ref(), String::new()),
+        };
+        rules::Event {
+            kind: "create".to_string(),
+            branch,
+            tag,
+            action: self.ref_type(),
+            ..Default::default()
+        }
+    }
+
+    pub fn git_source(&self) -> Source {
+        match self.is_tag() {
+            true => self.repository.git_source(Ref::Tag(self.r#ref())),
+            false => self.repository.git_source(Ref::Branch(self.r#ref())),
+        }
+    }
+
+    pub fn dump(&self) {
+        let log = slog_scope::logger();
+
+        debug!(log, "github webhook event"; "ref_type" => self.ref_type());
+        self.repository.dump();
+        debug!(log, "github webhook create"; "ref" => self.r#ref());
+        debug!(log, "github webhook sender"; "login" => self.sender());
+    }
+}
+
+impl WorkflowRunEvent {
+    pub fn action(&self) -> String {
+        value_or_undefined(&self.action)
+    }
+
+    pub fn name(&self) -> String {
+        value_or_undefined(&self.workflow_run.name)
+    }
+
+    pub fn head_branch(&self) -> String {
+        value_or_undefined(&self.workflow_run.head_branch)
+    }
+
+    pub fn head_sha(&self) -> String {
+        value_or_undefined(&self.workflow_run.head_sha)
+    }
+
+    pub fn status(&self) -> String {
+        value_or_undefined(&self.workflow_run.status)
+    }
+
+    pub fn conclusion(&self) -> String {
+        value_or_undefined(&self.workflow_run.conclusion)
+    }
+
+    /// Name of the event that triggered the workflow, eg. `push`.
+    pub fn trigger(&self) -> String {
+        value_or_undefined(&self.workflow_run.event)
+    }
+
+    pub fn sender(&self) -> String {
+        User::login_of(&self.sender)
+    }
+
+    /// Normalized event for deploy rule evaluation, the conclusion of the run
+    /// is used as the state.
+    pub fn event(&self) -> rules::Event {
+        rules::Event {
+            kind: "workflow_run".to_string(),
+            branch: self.head_branch(),
+            action: self.action(),
+            state: self.conclusion(),
+            workflow: self.name(),
+            ..Default::default()
+        }
+    }
+
+    /// Source of the head branch of the run at the commit it ran for.
+    pub fn git_source(&self) -> Source {
+        Source {
+            sha: self.workflow_run.head_sha.clone(),
+            ..self.repository.git_source(Ref::Branch(self.head_branch()))
+        }
+    }
+
+    pub fn dump(&self) {
+        let log = slog_scope::logger();
+
+        debug!(log, "github webhook event"; "action" => self.action());
+        self.repository.dump();
+        debug!(log, "github webhook workflow_run";
+            "name" => self.name(),
+            "head_branch" => self.head_branch(),
+            "head_sha" => self.head_sha(),
+            "status" => self.status(),
+            "conclusion" => self.conclusion(),
+            "event" => self.trigger(),
+        );
+        debug!(log, "github webhook sender"; "login" => self.sender());
+    }
+}
+
+impl CheckSuiteEvent {
+    pub fn action(&self) -> String {
+        value_or_undefined(&self.action)
+    }
+
+    /// Name of the app that ran the check suite, eg. `GitHub Actions`.
+    pub fn app(&self) -> String {
+        match &self.check_suite.app {
+            None => "undefined".to_string(),
+            Some(app) => value_or_undefined(&app.name),
+        }
+    }
+
+    pub fn head_branch(&self) -> String {
+        value_or_undefined(&self.check_suite.head_branch)
+    }
+
+    pub fn head_sha(&self) -> String {
+        value_or_undefined(&self.check_suite.head_sha)
+    }
+
+    pub fn status(&self) -> String {
+        value_or_undefined(&self.check_suite.status)
+    }
+
+    pub fn conclusion(&self) -> String {
+        value_or_undefined(&self.check_suite.conclusion)
+    }
+
+    pub fn sender(&self) -> String {
+        User::login_of(&self.sender)
+    }
+
+    /// Normalized event for deploy rule evaluation, the app name is used as
+    /// the workflow and the conclusion as the state.
+    pub fn event(&self) -> rules::Event {
+        rules::Event {
+            kind: "check_suite".to_string(),
+            branch: self.head_branch(),
+            action: self.action(),
+            state: self.conclusion(),
+            workflow: self.app(),
+            ..Default::default()
+        }
+    }
+
+    /// Source of the head branch of the check suite at the commit it ran for.
+    pub fn git_source(&self) -> Source {
+        Source {
+            sha: self.check_suite.head_sha.clone(),
+            ..self.repository.git_source(Ref::Branch(self.head_branch()))
+        }
+    }
+
+    pub fn dump(&self) {
+        let log = slog_scope::logger();
+
+        debug!(log, "github webhook event"; "action" => self.action());
+        self.repository.dump();
+        debug!(log, "github webhook check_suite";
+            "app" => self.app(),
+            "head_branch" => self.head_branch(),
+            "head_sha" => self.head_sha(),
+            "status" => self.status(),
+            "conclusion" => self.conclusion(),
+        );
+        debug!(log, "github webhook sender"; "login" => self.sender());
+    }
+}
+
+impl Event {
+    /// Parse the body for the event named in the `X-GitHub-Event` header,
+    /// `None` is returned for events that aren't handled.
+    pub fn parse(name: &str, body: &[u8]) -> Result<Option<Self>, serde_json::Error> {
+        match name {
+            "pull_request" => Ok(Some(Event::PullRequest(serde_json::from_slice(body)?))),
+            "push" => Ok(Some(Event::Push(serde_json::from_slice(body)?))),
+            "release" => Ok(Some(Event::Release(serde_json::from_slice(body)?))),
+            "create" => Ok(Some(Event::Create(serde_json::from_slice(body)?))),
+            "workflow_run" => Ok(Some(Event::WorkflowRun(serde_json::from_slice(body)?))),
+            "check_suite" => Ok(Some(Event::CheckSuite(serde_json::from_slice(body)?))),
+            _ => Ok(None),
+        }
+    }
+
+    pub fn event(&self) -> rules::Event {
+        match self {
+            Event::PullRequest(webhook) => webhook.event(),
+            Event::Push(push) => push.event(),
+            Event::Release(release) => release.event(),
+            Event::Create(create) => create.event(),
+            Event::WorkflowRun(run) => run.event(),
+            Event::CheckSuite(suite) => suite.event(),
+        }
+    }
+
+    /// Commit the event deploys, `undefined` for events without one.
+    pub fn sha(&self) -> String {
+        value_or_undefined(&self.git_source().sha)
+    }
+
+    /// Environment variables describing the event for the project commands,
+    /// release events also include the release metadata.
+    pub fn env(&self) -> HashMap<String, String> {
+        match self {
+            Event::Release(release) => release.env(),
+            _ => self.event().env(),
+        }
+    }
+
+    /// Repository revision the event deploys.
+    pub fn git_source(&self) -> Source {
+        match self {
+            Event::PullRequest(webhook) => webhook.git_source(),
+            Event::Push(push) => push.git_source(),
+            Event::Release(release) => release.git_source(),
+            Event::Create(create) => create.git_source(),
+            Event::WorkflowRun(run) => run.git_source(),
+            Event::CheckSuite(suite) => suite.git_source(),
+        }
+    }
+
+    pub fn dump(&self) {
+        match self {
+            Event::PullRequest(webhook) => webhook.dump(),
+            Event::Push(push) => push.dump(),
+            Event::Release(release) => release.dump(),
+            Event::Create(create) => create.dump(),
+            Event::WorkflowRun(run) => run.dump(),
+            Event::CheckSuite(suite) => suite.dump(),
+        }
+    }
+}
+
 /// Verify the GitHub webhook signature using HMAC-SHA256
 pub fn verify_signature(secret: &str, body: &[u8], signature: &str) -> bool {
     // GitHub signature format: "sha256=<hex_digest>"
@@ -261,8 +911,8 @@ pub fn verify_signature(secret: &str, body: &[u8], signature: &str) -> bool {
 
     let signature_hex = &signature[7..];
 
-    let mut mac = HmacSha256::new_from_slice(secret.as_bytes())
-        .expect("HMAC can take key of any size");
+    let mut mac =
+        HmacSha256::new_from_slice(secret.as_bytes()).expect("HMAC can take key of any size");
     mac.update(body);
 
     let result = mac.finalize();
@@ -272,12 +922,6 @@ pub fn verify_signature(secret: &str, body: &[u8], signature: &str) -> bool {
     expected == signature_hex
 }
 
-/// Check if this webhook event should trigger a deployment
-pub fn should_deploy_github(action: String, merged: bool, target_branch: String) -> bool {
-    // GitHub sends "closed" action when PR is merged
-    action == "closed" && merged && target_branch == "main"
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -299,6 +943,7 @@ mod tests {
                 "title": "Test PR",
                 "merged": true,
                 "merged_at": "2023-01-01T00:00:00Z",
+                "merge_commit_sha": "fed789",
                 "head": {
                     "ref": "feature-branch",
                     "sha": "abc123"
@@ -318,13 +963,24 @@ mod tests {
         assert_eq!(webhook.action(), "closed".to_string());
         assert_eq!(webhook.repository_name(), "test-repo".to_string());
         assert_eq!(webhook.repository_full_name(), "user/test-repo".to_string());
-        assert_eq!(webhook.clone_url(), "https://github.com/user/test-repo.git".to_string());
-        assert_eq!(webhook.ssh_url(), "git@github.com:user/test-repo.git".to_string());
+        assert_eq!(
+            webhook.clone_url(),
+            "https://github.com/user/test-repo.git".to_string()
+        );
+        assert_eq!(
+            webhook.ssh_url(),
+            "git@github.com:user/test-repo.git".to_string()
+        );
         assert_eq!(webhook.default_branch(), "main".to_string());
         assert!(webhook.is_merged());
         assert_eq!(webhook.pr_state(), "closed".to_string());
         assert_eq!(webhook.target_branch(), "main".to_string());
         assert_eq!(webhook.source_branch(), "feature-branch".to_string());
+        assert_eq!(webhook.head_sha(), "abc123".to_string());
+        assert_eq!(webhook.base_sha(), "def456".to_string());
+        assert_eq!(webhook.merged_at(), "2023-01-01T00:00:00Z".to_string());
+        assert_eq!(webhook.merge_commit_sha(), "fed789".to_string());
+        assert_eq!(webhook.git_source().sha, Some("fed789".to_string()));
         assert_eq!(webhook.pr_number(), 123);
         assert_eq!(webhook.pr_title(), "Test PR".to_string());
         assert_eq!(webhook.sender(), "testuser".to_string());
@@ -335,7 +991,8 @@ mod tests {
         let secret = "test_secret";
         let body = b"test payload";
         // This is the actual HMAC-SHA256 signature for "test payload" with secret "test_secret"
-        let valid_signature = "sha256=fb9fb46a0a4c5edf7c9f524414be12d1eef6847c7b34dac98757920731e51169";
+        let valid_signature =
+            "sha256=fb9fb46a0a4c5edf7c9f524414be12d1eef6847c7b34dac98757920731e51169";
 
         assert!(verify_signature(secret, body, valid_signature));
         assert!(!verify_signature(secret, body, "sha256=invalid"));
@@ -343,26 +1000,275 @@ mod tests {
     }
 
     #[test]
-    fn it_should_deploy() {
-        assert!(should_deploy_github(
-            "closed".to_string(),
-            true,
-            "main".to_string()
-        ));
-        assert!(!should_deploy_github(
-            "opened".to_string(),
-            false,
-            "main".to_string()
-        ));
-        assert!(!should_deploy_github(
-            "closed".to_string(),
-            false,
-            "main".to_string()
-        ));
-        assert!(!should_deploy_github(
-            "closed".to_string(),
-            true,
-            "develop".to_string()
-        ));
-    }
-}
\ No newline at end of file
+    fn it_normalizes_event() {
+        let input = r#"{
+            "action": "closed",
+            "repository": {
+                "name": "test-repo"
+            },
+            "pull_request": {
+                "state": "closed",
+                "merged": true,
+                "head": {
+                    "ref": "feature-branch"
+                },
+                "base": {
+                    "ref": "release/1.0"
+                }
+            }
+        }"#;
+
+        let webhook = serde_json::from_str::<Webhook>(input).unwrap();
+        let event = webhook.event();
+
+        assert_eq!(event.branch, "release/1.0".to_string());
+        assert_eq!(event.action, "closed".to_string());
+        assert_eq!(event.state, "merged".to_string());
+    }
+
+    #[test]
+    fn it_deserializes_push_event() {
+        let input = r#"{
+            "ref": "refs/heads/release/1.0",
+            "before": "abc123",
+            "after": "def456",
+            "created": false,
+            "deleted": false,
+            "commits": [
+                {
+                    "id": "cba321",
+                    "message": "First commit"
+                },
+                {
+                    "id": "def456",
+                    "message": "Second commit"
+                }
+            ],
+            "repository": {
+                "name": "test-repo",
+                "full_name": "user/test-repo",
+                "clone_url": "https://github.com/user/test-repo.git",
+                "default_branch": "main"
+            },
+            "pusher": {
+                "name": "testuser",
+                "email": "testuser@example.com"
+            },
+            "sender": {
+                "login": "testuser"
+            }
+        }"#;
+
+        let push = serde_json::from_str::<Push>(input).unwrap();
+
+        assert_eq!(push.r#ref(), "refs/heads/release/1.0".to_string());
+        assert_eq!(push.branch(), "release/1.0".to_string());
+        assert_eq!(push.tag(), "".to_string());
+        assert_eq!(push.before(), "abc123".to_string());
+        assert_eq!(push.after(), "def456".to_string());
+        assert_eq!(push.action(), "pushed".to_string());
+        assert_eq!(push.commit_ids(), vec!["cba321", "def456"]);
+        assert_eq!(push.head_commit_message(), "Second commit".to_string());
+        assert_eq!(push.pusher(), "testuser".to_string());
+        assert_eq!(push.pusher_email(), "testuser@example.com".to_string());
+        assert_eq!(push.sender(), "testuser".to_string());
+
+        let event = push.event();
+        assert_eq!(event.kind, "push".to_string());
+        assert_eq!(event.branch, "release/1.0".to_string());
+        assert_eq!(event.action, "pushed".to_string());
+        assert_eq!(push.git_source().sha, Some("def456".to_string()));
+    }
+
+    #[test]
+    fn it_builds_manual_push() {
+        let push = Push::manual(
+            "https://github.com/owner/repo.git",
+            Some("staging"),
+            Some("abc123"),
+        );
+
+        assert_eq!(push.branch(), "staging".to_string());
+        assert_eq!(push.after(), "abc123".to_string());
+        assert_eq!(push.repository.name(), "repo".to_string());
+        assert_eq!(push.event().kind, "push".to_string());
+        assert_eq!(push.event().branch, "staging".to_string());
+
+        let source = push.git_source();
+        assert_eq!(source.url, "https://github.com/owner/repo.git".to_string());
+        assert_eq!(source.path, "owner/repo".to_string());
+        assert_eq!(source.reference, Ref::Branch("staging".to_string()));
+    }
+
+    #[test]
+    fn it_parses_events_by_name() {
+        let push = r#"{
+            "ref": "refs/heads/main",
+            "deleted": true,
+            "repository": {
+                "name": "test-repo"
+            }
+        }"#;
+        let pull_request = r#"{
+            "action": "opened",
+            "repository": {
+                "name": "test-repo"
+            }
+        }"#;
+
+        match Event::parse("push", push.as_bytes()).unwrap() {
+            Some(Event::Push(push)) => {
+                assert_eq!(push.action(), "deleted".to_string());
+                assert_eq!(push.git_source().sha, None);
+            }
+            _ => panic!("expected push event"),
+        }
+        match Event::parse("pull_request", pull_request.as_bytes()).unwrap() {
+            Some(Event::PullRequest(webhook)) => {
+                assert_eq!(webhook.action(), "opened".to_string())
+            }
+            _ => panic!("expected pull_request event"),
+        }
+        assert!(Event::parse("ping", b"{}").unwrap().is_none());
+        assert!(Event::parse("push", b"{}").is_err());
+    }
+
+    #[test]
+    fn it_deserializes_release_event() {
+        let input = r#"{
+            "action": "published",
+            "release": {
+                "tag_name": "v1.2.0",
+                "name": "Version 1.2.0",
+                "target_commitish": "main",
+                "html_url": "https://github.com/user/test-repo/releases/tag/v1.2.0",
+                "draft": false,
+                "prerelease": true
+            },
+            "repository": {
+                "name": "test-repo"
+            },
+            "sender": {
+                "login": "testuser"
+            }
+        }"#;
+
+        let release = serde_json::from_str::<ReleaseEvent>(input).unwrap();
+        let event = release.event();
+        let env = release.env();
+
+        assert_eq!(release.tag_name(), "v1.2.0".to_string());
+        assert_eq!(
+            release.git_source().reference,
+            Ref::Tag("v1.2.0".to_string())
+        );
+        assert_eq!(release.name(), "Version 1.2.0".to_string());
+        assert!(release.is_prerelease());
+        assert!(!release.is_draft());
+        assert_eq!(event.kind, "release".to_string());
+        assert_eq!(event.tag, "v1.2.0".to_string());
+        assert_eq!(event.action, "published".to_string());
+        assert_eq!(event.state, "prerelease".to_string());
+        assert_eq!(env.get("SHOOK_TAG"), Some(&"v1.2.0".to_string()));
+        assert_eq!(
+            env.get("SHOOK_RELEASE_NAME"),
+            Some(&"Version 1.2.0".to_string())
+        );
+        assert_eq!(
+            env.get("SHOOK_RELEASE_PRERELEASE"),
+            Some(&"true".to_string())
+        );
+
+        let input = r#"{"release": {"tag_name": "v1.2.0"}, "repository": {}}"#;
+        let env = serde_json::from_str::<ReleaseEvent>(input).unwrap().env();
+        assert_eq!(env.get("SHOOK_RELEASE_NAME"), None);
+        assert_eq!(env.get("SHOOK_RELEASE_URL"), None);
+        assert_eq!(env.get("SHOOK_RELEASE_TARGET"), None);
+    }
+
+    #[test]
+    fn it_deserializes_create_event() {
+        let input = r#"{
+            "ref": "v1.2.0",
+            "ref_type": "tag",
+            "repository": {
+                "name": "test-repo"
+            }
+        }"#;
+
+        match Event::parse("create", input.as_bytes()).unwrap() {
+            Some(Event::Create(create)) => {
+                let event = create.event();
+                assert!(create.is_tag());
+                assert_eq!(event.kind, "create".to_string());
+                assert_eq!(event.branch, "".to_string());
+                assert_eq!(event.tag, "v1.2.0".to_string());
+                assert_eq!(event.action, "tag".to_string());
+            }
+            _ => panic!("expected create event"),
+        }
+    }
+
+    #[test]
+    fn it_deserializes_workflow_run_event() {
+        let input = r#"{
+            "action": "completed",
+            "workflow_run": {
+                "name": "CI",
+                "head_branch": "main",
+                "head_sha": "abc123",
+                "status": "completed",
+                "conclusion": "success",
+                "event": "push"
+            },
+            "repository": {
+                "name": "test-repo"
+            }
+        }"#;
+
+        match Event::parse("workflow_run", input.as_bytes()).unwrap() {
+            Some(Event::WorkflowRun(run)) => {
+                let event = run.event();
+                assert_eq!(run.head_sha(), "abc123".to_string());
+                assert_eq!(run.trigger(), "push".to_string());
+                assert_eq!(event.kind, "workflow_run".to_string());
+                assert_eq!(event.branch, "main".to_string());
+                assert_eq!(event.action, "completed".to_string());
+                assert_eq!(event.state, "success".to_string());
+                assert_eq!(event.workflow, "CI".to_string());
+                assert!(event.is_successful());
+            }
+            _ => panic!("expected workflow_run event"),
+        }
+    }
+
+    #[test]
+    fn it_deserializes_check_suite_event() {
+        let input = r#"{
+            "action": "completed",
+            "check_suite": {
+                "head_branch": "main",
+                "head_sha": "abc123",
+                "status": "completed",
+                "conclusion": "failure",
+                "app": {
+                    "name": "GitHub Actions"
+                }
+            },
+            "repository": {
+                "name": "test-repo"
+            }
+        }"#;
+
+        match Event::parse("check_suite", input.as_bytes()).unwrap() {
+            Some(Event::CheckSuite(suite)) => {
+                let event = suite.event();
+                assert_eq!(event.kind, "check_suite".to_string());
+                assert_eq!(event.workflow, "GitHub Actions".to_string());
+                assert_eq!(event.state, "failure".to_string());
+                assert!(!event.is_successful());
+            }
+            _ => panic!("expected check_suite event"),
+        }
+    }
+}
@@ -1,7 +1,12 @@
 use serde::Deserialize;
-use std::env;
-use std::io;
-use std::path::Path;
+use std::collections::HashMap;
+
+use crate::git::{self, Ref, Source};
+use crate::rules;
+
+/// SHA GitLab uses for `before` when a ref is created and `after` when a ref is
+/// deleted.
+const BLANK_SHA: &str = "0000000000000000000000000000000000000000";
 
 #[derive(Deserialize)]
 struct Repository {
@@ -23,8 +28,28 @@ struct Attributes {
     source_branch: Option<String>,
     state: Option<String>,
     merge_status: Option<String>,
+    merge_commit_sha: Option<String>,
+    last_commit: Option<Commit>,
+}
+
+#[derive(Deserialize)]
+struct Commit {
+    id: Option<String>,
+    message: Option<String>,
 }
 
+#[derive(Deserialize)]
+struct PipelineAttributes {
+    id: Option<u64>,
+    r#ref: Option<String>,
+    #[serde(default)]
+    tag: bool,
+    sha: Option<String>,
+    source: Option<String>,
+    status: Option<String>,
+}
+
+/// Payload of a `Merge Request Hook` event.
 #[derive(Deserialize)]
 pub struct Webhook {
     event_type: Option<String>,
@@ -33,23 +58,56 @@ pub struct Webhook {
     object_attributes: Attributes,
 }
 
-impl Webhook {
-    pub fn event_type(&self) -> String {
-        match &self.event_type {
-            None => "undefined".to_string(),
-            Some(value) => value.clone(),
-        }
+/// Payload of a `Push Hook` or `Tag Push Hook` event.
+#[derive(Deserialize)]
+pub struct Push {
+    object_kind: Option<String>,
+    r#ref: Option<String>,
+    before: Option<String>,
+    after: Option<String>,
+    checkout_sha: Option<String>,
+    user_username: Option<String>,
+    #[serde(default)]
+    commits: Vec<Commit>,
+    project: Project,
+    repository: Repository,
+}
+
+/// Payload of a `Pipeline Hook` event.
+#[derive(Deserialize)]
+pub struct Pipeline {
+    project: Project,
+    object_attributes: PipelineAttributes,
+}
+
+/// Supported GitLab events, selected by the `X-Gitlab-Event` header.
+pub enum Event {
+    MergeRequest(Webhook),
+    Push(Push),
+    TagPush(Push),
+    Pipeline(Pipeline),
+}
+
+fn value_or_undefined(value: &Option<String>) -> String {
+    match value {
+        None => "undefined".to_string(),
+        Some(value) => value.clone(),
     }
+}
 
-    pub fn default_branch(&self) -> String {
-        match &self.project.default_branch {
-            None => "undefined".to_string(),
-            Some(value) => value.clone(),
-        }
+impl Repository {
+    fn url(&self) -> String {
+        value_or_undefined(&self.url)
+    }
+}
+
+impl Project {
+    fn default_branch(&self) -> String {
+        value_or_undefined(&self.default_branch)
     }
 
-    pub fn project_namespace(&self) -> String {
-        match &self.project.path_with_namespace {
+    fn namespace(&self) -> String {
+        match &self.path_with_namespace {
             None => "undefined".to_string(),
             Some(value) => {
                 let parts = value.split('/').collect::<Vec<&str>>();
@@ -58,8 +116,8 @@ impl Webhook {
         }
     }
 
-    pub fn project_name(&self) -> String {
-        match &self.project.path_with_namespace {
+    fn name(&self) -> String {
+        match &self.path_with_namespace {
             None => "undefined".to_string(),
             Some(value) => {
                 let parts = value.split('/').collect::<Vec<&str>>();
@@ -68,25 +126,71 @@ impl Webhook {
         }
     }
 
-    pub fn ssh_url(&self) -> String {
-        match &self.project.git_ssh_url {
-            None => "undefined".to_string(),
-            Some(value) => value.clone(),
+    fn ssh_url(&self) -> String {
+        value_or_undefined(&self.git_ssh_url)
+    }
+
+    fn http_url(&self) -> String {
+        value_or_undefined(&self.git_http_url)
+    }
+
+    fn path_with_namespace(&self) -> String {
+        value_or_undefined(&self.path_with_namespace)
+    }
+
+    /// Source of the repository at `reference`, cloned from the HTTP URL of the
+    /// project or from `url` when it has none. The checkout is kept under the
+    /// path of the project with its namespace.
+    fn git_source(&self, url: String, reference: Ref) -> Source {
+        Source {
+            url: self.git_http_url.clone().unwrap_or(url),
+            ssh_url: self.git_ssh_url.clone(),
+            path: self.path_with_namespace(),
+            reference,
+            sha: None,
         }
     }
 
+    fn dump(&self) {
+        let log = slog_scope::logger();
+
+        debug!(log, "webhook project";
+            "name" => self.name(),
+            "namespace" => self.namespace(),
+            "default_branch" => self.default_branch(),
+            "ssh_url" => self.ssh_url(),
+            "http_url" => self.http_url(),
+        );
+    }
+}
+
+impl Webhook {
+    pub fn event_type(&self) -> String {
+        value_or_undefined(&self.event_type)
+    }
+
+    pub fn default_branch(&self) -> String {
+        self.project.default_branch()
+    }
+
+    pub fn project_namespace(&self) -> String {
+        self.project.namespace()
+    }
+
+    pub fn project_name(&self) -> String {
+        self.project.name()
+    }
+
+    pub fn ssh_url(&self) -> String {
+        self.project.ssh_url()
+    }
+
     pub fn http_url(&self) -> String {
-        match &self.project.git_http_url {
-            None => "undefined".to_string(),
-            Some(value) => value.clone(),
-        }
+        self.project.http_url()
     }
 
     pub fn repository_url(&self) -> String {
-        match &self.repository.url {
-            None => "undefined".to_string(),
-            Some(value) => value.clone(),
-        }
+        self.repository.url()
     }
 
     pub fn action(&self) -> String {
@@ -117,6 +221,11 @@ impl Webhook {
         }
     }
 
+    /// Whether the merge request was merged, rather than opened or updated.
+    pub fn is_merged(&self) -> bool {
+        self.object_attributes.state.as_deref() == Some("merged")
+    }
+
     pub fn merge_status(&self) -> String {
         match &self.object_attributes.merge_status {
             None => "undefined".to_string(),
@@ -124,69 +233,44 @@ impl Webhook {
         }
     }
 
-    fn fast_forward(&self, path: &Path) -> Result<(), git2::Error> {
-        let repo = git2::Repository::open(path)?;
-
-        repo.find_remote("origin")?
-            .fetch(&[self.default_branch()], None, None)?;
-
-        let fetch_head = repo.find_reference("FETCH_HEAD")?;
-        let fetch_commit = repo.reference_to_annotated_commit(&fetch_head)?;
-        let analysis = repo.merge_analysis(&[&fetch_commit])?;
+    pub fn merge_commit_sha(&self) -> String {
+        value_or_undefined(&self.object_attributes.merge_commit_sha)
+    }
 
-        if analysis.0.is_up_to_date() {
-            Ok(())
-        } else if analysis.0.is_fast_forward() {
-            let refname = format!("refs/heads/{}", self.default_branch());
-            let mut reference = repo.find_reference(&refname)?;
+    pub fn last_commit_sha(&self) -> String {
+        match &self.object_attributes.last_commit {
+            None => "undefined".to_string(),
+            Some(commit) => value_or_undefined(&commit.id),
+        }
+    }
 
-            reference.set_target(fetch_commit.id(), "Fast-Forward")?;
-            repo.set_head(&refname)?;
-            repo.checkout_head(Some(git2::build::CheckoutBuilder::default().force()))
-        } else {
-            Err(git2::Error::from_str("Fast-forward only!"))
+    /// Normalized event for deploy rule evaluation.
+    pub fn event(&self) -> rules::Event {
+        rules::Event {
+            kind: "merge_request".to_string(),
+            branch: self.target_branch(),
+            action: self.action(),
+            state: self.merge_status(),
+            ..Default::default()
         }
     }
 
-    fn reset(&self, path: &Path) {
-        let repo = match git2::Repository::open(path) {
-            Ok(repo) => repo,
-            Err(e) => panic!("Failed to open: {}", e),
+    /// Source of the target branch of the merge request, at the merge commit
+    /// once it was merged. Fast-forward merges have no merge commit, the last
+    /// commit of the merge request is the one that was merged then. Unmerged
+    /// requests deploy the head of the target branch.
+    pub fn git_source(&self) -> Source {
+        let attributes = &self.object_attributes;
+        let last_commit = match self.is_merged() {
+            true => attributes.last_commit.as_ref().and_then(|c| c.id.clone()),
+            false => None,
         };
-        repo.reset(
-            &repo.revparse_single("HEAD").unwrap(),
-            git2::ResetType::Hard,
-            None,
-        )
-        .unwrap();
-    }
-
-    pub fn clone_repository(&self) -> Result<String, io::Error> {
-        let project = self.project_name();
-        let base = "/var/cache/shook/".to_string();
-        let path = format!("{}{}", base, project);
-        let repo_path = Path::new(&path);
-
-        match repo_path.exists() && repo_path.is_dir() {
-            true => {
-                let root = Path::new(&path);
-                env::set_current_dir(&root)?;
-                self.reset(repo_path);
-                if let Err(e) = self.fast_forward(repo_path) {
-                    panic!("Failed to pull: {}", e)
-                }
-            }
-            false => {
-                let root = Path::new(&base);
-                env::set_current_dir(&root)?;
-                let _ = match git2::Repository::clone(&self.repository_url(), &project) {
-                    Ok(repo) => repo,
-                    Err(e) => panic!("failed to clone repository: {}", e),
-                };
-            }
+        Source {
+            sha: attributes.merge_commit_sha.clone().or(last_commit),
+            ..self
+                .project
+                .git_source(self.repository_url(), Ref::Branch(self.target_branch()))
         }
-
-        Ok(format!("/var/cache/shook/{}", project))
     }
 
     pub fn dump(&self) {
@@ -196,6 +280,7 @@ impl Webhook {
         debug!(log, "webhook project";
             "name" => self.project_name(),
             "namespace" => self.project_namespace(),
+            "default_branch" => self.default_branch(),
             "ssh_url" => self.ssh_url(),
             "http_url" => self.http_url(),
         );
@@ -206,10 +291,277 @@ impl Webhook {
             "source_branch" => self.source_branch(),
             "state" => self.state(),
             "merge_status" => self.merge_status(),
+            "merge_commit_sha" => self.merge_commit_sha(),
+            "last_commit_sha" => self.last_commit_sha(),
+        );
+    }
+}
+
+impl Push {
+    /// Push of `branch`, or of no branch, for a manual deployment of the repository at the clone
+    /// URL, the path of the URL is the path of the project with its namespace.
+    pub fn manual(repository: &str, branch: Option<&str>, sha: Option<&str>) -> Self {
+        Push {
+            object_kind: Some("push".to_string()),
+            r#ref: branch.map(|branch| format!("refs/heads/{}", branch)),
+            before: None,
+            after: sha.map(str::to_string),
+            checkout_sha: sha.map(str::to_string),
+            user_username: None,
+            commits: Vec::new(),
+            project: Project {
+                default_branch: branch.map(str::to_string),
+                git_ssh_url: None,
+                git_http_url: Some(repository.to_string()),
+                path_with_namespace: git::repository_path(repository),
+            },
+            repository: Repository {
+                url: Some(repository.to_string()),
+            },
+        }
+    }
+
+    pub fn object_kind(&self) -> String {
+        value_or_undefined(&self.object_kind)
+    }
+
+    pub fn r#ref(&self) -> String {
+        value_or_undefined(&self.r#ref)
+    }
+
+    /// Branch name of the pushed ref, empty for tag pushes.
+    pub fn branch(&self) -> String {
+        match self.r#ref().strip_prefix("refs/heads/") {
+            None => String::new(),
+            Some(branch) => branch.to_string(),
+        }
+    }
+
+    /// Tag name of the pushed ref, empty for branch pushes.
+    pub fn tag(&self) -> String {
+        match self.r#ref().strip_prefix("refs/tags/") {
+            None => String::new(),
+            Some(tag) => tag.to_string(),
+        }
+    }
+
+    pub fn before(&self) -> String {
+        value_or_undefined(&self.before)
+    }
+
+    pub fn after(&self) -> String {
+        value_or_undefined(&self.after)
+    }
+
+    pub fn checkout_sha(&self) -> String {
+        value_or_undefined(&self.checkout_sha)
+    }
+
+    /// Either `created`, `deleted` or `pushed` for updates of an existing ref.
+    pub fn action(&self) -> String {
+        if self.after() == BLANK_SHA {
+            "deleted".to_string()
+        } else if self.before() == BLANK_SHA {
+            "created".to_string()
+        } else {
+            "pushed".to_string()
+        }
+    }
+
+    pub fn commit_ids(&self) -> Vec<String> {
+        self.commits
+            .iter()
+            .map(|commit| value_or_undefined(&commit.id))
+            .collect()
+    }
+
+    pub fn head_commit_message(&self) -> String {
+        match self.commits.last() {
+            None => "undefined".to_string(),
+            Some(commit) => value_or_undefined(&commit.message),
+        }
+    }
+
+    pub fn user(&self) -> String {
+        value_or_undefined(&self.user_username)
+    }
+
+    pub fn repository_url(&self) -> String {
+        self.repository.url()
+    }
+
+    /// Normalized event for deploy rule evaluation, the kind is `push` or
+    /// `tag_push`.
+    pub fn event(&self) -> rules::Event {
+        let kind = match self.tag().is_empty() {
+            true => "push",
+            false => "tag_push",
+        };
+        rules::Event {
+            kind: kind.to_string(),
+            branch: self.branch(),
+            tag: self.tag(),
+            action: self.action(),
+            ..Default::default()
+        }
+    }
+
+    /// Source of the pushed branch or tag at the pushed commit, the default
+    /// branch for refs that are neither.
+    pub fn git_source(&self) -> Source {
+        let reference = match (self.branch(), self.tag()) {
+            (branch, _) if !branch.is_empty() => Ref::Branch(branch),
+            (_, tag) if !tag.is_empty() => Ref::Tag(tag),
+            _ => Ref::Branch(self.project.default_branch()),
+        };
+        let sha = self
+            .checkout_sha
+            .clone()
+            .or_else(|| self.after.clone().filter(|sha| sha != BLANK_SHA));
+        Source {
+            sha,
+            ..self.project.git_source(self.repository_url(), reference)
+        }
+    }
+
+    pub fn dump(&self) {
+        let log = slog_scope::logger();
+
+        debug!(log, "webhook event"; "object_kind" => self.object_kind());
+        self.project.dump();
+        debug!(log, "webhook repository"; "url" => self.repository_url());
+        debug!(log, "webhook push";
+            "ref" => self.r#ref(),
+            "before" => self.before(),
+            "after" => self.after(),
+            "checkout_sha" => self.checkout_sha(),
+            "commits" => self.commit_ids().join(","),
+            "head_commit_message" => self.head_commit_message(),
+            "user" => self.user(),
+        );
+    }
+}
+
+impl Pipeline {
+    pub fn id(&self) -> u64 {
+        self.object_attributes.id.unwrap_or(0)
+    }
+
+    pub fn r#ref(&self) -> String {
+        value_or_undefined(&self.object_attributes.r#ref)
+    }
+
+    pub fn is_tag(&self) -> bool {
+        self.object_attributes.tag
+    }
+
+    pub fn sha(&self) -> String {
+        value_or_undefined(&self.object_attributes.sha)
+    }
+
+    pub fn source(&self) -> String {
+        value_or_undefined(&self.object_attributes.source)
+    }
+
+    pub fn status(&self) -> String {
+        value_or_undefined(&self.object_attributes.status)
+    }
+
+    /// Normalized event for deploy rule evaluation, the pipeline source is
+    /// used as the action and the pipeline status as the state.
+    pub fn event(&self) -> rules::Event {
+        let (branch, tag) = match self.is_tag() {
+            true => (String::new(), self.r#ref()),
+            false => (self.r#ref(), String::new()),
+        };
+        rules::Event {
+            kind: "pipeline".to_string(),
+            branch,
+            tag,
+            action: self.source(),
+            state: self.status(),
+            ..Default::default()
+        }
+    }
+
+    /// Source of the ref at the commit the pipeline ran for, pipeline events
+    /// don't include the repository so there's no URL to fall back on.
+    pub fn git_source(&self) -> Source {
+        let reference = match self.is_tag() {
+            true => Ref::Tag(self.r#ref()),
+            false => Ref::Branch(self.r#ref()),
+        };
+        Source {
+            sha: self.object_attributes.sha.clone(),
+            ..self.project.git_source(self.project.http_url(), reference)
+        }
+    }
+
+    pub fn dump(&self) {
+        let log = slog_scope::logger();
+
+        debug!(log, "webhook event"; "object_kind" => "pipeline");
+        self.project.dump();
+        debug!(log, "webhook pipeline";
+            "id" => self.id(),
+            "ref" => self.r#ref(),
+            "tag" => self.is_tag(),
+            "sha" => self.sha(),
+            "source" => self.source(),
+            "status" => self.status(),
         );
     }
 }
 
+impl Event {
+    /// Parse the body for the event named in the `X-Gitlab-Event` header,
+    /// `None` is returned for events that aren't handled.
+    pub fn parse(name: &str, body: &[u8]) -> Result<Option<Self>, serde_json::Error> {
+        match name {
+            "Merge Request Hook" => Ok(Some(Event::MergeRequest(serde_json::from_slice(body)?))),
+            "Push Hook" => Ok(Some(Event::Push(serde_json::from_slice(body)?))),
+            "Tag Push Hook" => Ok(Some(Event::TagPush(serde_json::from_slice(body)?))),
+            "Pipeline Hook" => Ok(Some(Event::Pipeline(serde_json::from_slice(body)?))),
+            _ => Ok(None),
+        }
+    }
+
+    pub fn event(&self) -> rules::Event {
+        match self {
+            Event::MergeRequest(webhook) => webhook.event(),
+            Event::Push(push) | Event::TagPush(push) => push.event(),
+            Event::Pipeline(pipeline) => pipeline.event(),
+        }
+    }
+
+    /// Commit the event deploys, `undefined` for events without one.
+    pub fn sha(&self) -> String {
+        value_or_undefined(&self.git_source().sha)
+    }
+
+    /// Environment variables describing the event for the project commands.
+    pub fn env(&self) -> HashMap<String, String> {
+        self.event().env()
+    }
+
+    /// Repository revision the event deploys.
+    pub fn git_source(&self) -> Source {
+        match self {
+            Event::MergeRequest(webhook) => webhook.git_source(),
+            Event::Push(push) | Event::TagPush(push) => push.git_source(),
+            Event::Pipeline(pipeline) => pipeline.git_source(),
+        }
+    }
+
+    pub fn dump(&self) {
+        match self {
+            Event::MergeRequest(webhook) => webhook.dump(),
+            Event::Push(push) | Event::TagPush(push) => push.dump(),
+            Event::Pipeline(pipeline) => pipeline.dump(),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -231,7 +583,11 @@ mod tests {
                 "target_branch": "main",
                 "source_branch": "staging",
                 "state": "merge",
-                "merge_status": "merged"
+                "merge_status": "merged",
+                "merge_commit_sha": "95790bf891e76fee5e1747ab589903a6a1f80f22",
+                "last_commit": {
+                    "id": "da1560886d4f094c3e6c9ef40349f7d38b5d27d7"
+                }
             }
         }"#;
         let webhook = serde_json::from_str::<Webhook>(input).unwrap();
@@ -255,7 +611,15 @@ mod tests {
         assert_eq!(webhook.target_branch(), "main".to_string());
         assert_eq!(webhook.source_branch(), "staging".to_string());
         assert_eq!(webhook.state(), "merge".to_string());
+        assert_eq!(
+            webhook.last_commit_sha(),
+            "da1560886d4f094c3e6c9ef40349f7d38b5d27d7".to_string()
+        );
         assert_eq!(webhook.merge_status(), "merged".to_string());
+        assert_eq!(
+            webhook.git_source().sha,
+            Some("95790bf891e76fee5e1747ab589903a6a1f80f22".to_string())
+        );
     }
 
     #[test]
@@ -285,5 +649,184 @@ mod tests {
         assert_eq!(webhook.source_branch(), "staging".to_string());
         assert_eq!(webhook.state(), "undefined".to_string());
         assert_eq!(webhook.merge_status(), "undefined".to_string());
+        assert_eq!(webhook.last_commit_sha(), "undefined".to_string());
+        assert_eq!(webhook.git_source().sha, None);
+    }
+
+    #[test]
+    fn it_checks_out_the_last_commit_of_fast_forward_merges() {
+        let input = r#"{
+            "project": {},
+            "repository": {
+                "url": "git@example.com/user/repo.git"
+            },
+            "object_attributes": {
+                "target_branch": "main",
+                "state": "merged",
+                "merge_commit_sha": null,
+                "last_commit": {
+                    "id": "da1560886d4f094c3e6c9ef40349f7d38b5d27d7"
+                }
+            }
+        }"#;
+        let webhook = serde_json::from_str::<Webhook>(input).unwrap();
+
+        assert!(webhook.is_merged());
+        assert_eq!(
+            webhook.git_source().sha,
+            Some("da1560886d4f094c3e6c9ef40349f7d38b5d27d7".to_string())
+        );
+
+        // the source branch of an open merge request isn't deployed
+        let opened = input.replace(r#""state": "merged""#, r#""state": "opened""#);
+        let webhook = serde_json::from_str::<Webhook>(&opened).unwrap();
+        assert!(!webhook.is_merged());
+        assert_eq!(webhook.git_source().sha, None);
+    }
+
+    #[test]
+    fn it_deserializes_push() {
+        let input = r#"{
+            "object_kind": "push",
+            "before": "95790bf891e76fee5e1747ab589903a6a1f80f22",
+            "after": "da1560886d4f094c3e6c9ef40349f7d38b5d27d7",
+            "ref": "refs/heads/master",
+            "checkout_sha": "da1560886d4f094c3e6c9ef40349f7d38b5d27d7",
+            "user_username": "jsmith",
+            "project": {
+                "path_with_namespace": "user/repo",
+                "default_branch": "master"
+            },
+            "repository": {
+                "url": "git@example.com/user/repo.git"
+            },
+            "commits": [
+                {
+                    "id": "da1560886d4f094c3e6c9ef40349f7d38b5d27d7",
+                    "message": "fixed readme"
+                }
+            ]
+        }"#;
+        let push = serde_json::from_str::<Push>(input).unwrap();
+
+        assert_eq!(push.object_kind(), "push".to_string());
+        assert_eq!(push.branch(), "master".to_string());
+        assert_eq!(push.tag(), "".to_string());
+        assert_eq!(
+            push.checkout_sha(),
+            "da1560886d4f094c3e6c9ef40349f7d38b5d27d7".to_string()
+        );
+        assert_eq!(push.action(), "pushed".to_string());
+        assert_eq!(push.head_commit_message(), "fixed readme".to_string());
+        assert_eq!(push.user(), "jsmith".to_string());
+
+        let event = push.event();
+        assert_eq!(event.kind, "push".to_string());
+        assert_eq!(event.branch, "master".to_string());
+        assert_eq!(
+            push.git_source().sha,
+            Some("da1560886d4f094c3e6c9ef40349f7d38b5d27d7".to_string())
+        );
+    }
+
+    #[test]
+    fn it_deserializes_tag_push() {
+        let input = r#"{
+            "object_kind": "tag_push",
+            "before": "0000000000000000000000000000000000000000",
+            "after": "82b3d5ae55f7080f1e6022629cdb57bfae7cccc7",
+            "ref": "refs/tags/v1.0.0",
+            "project": {
+                "path_with_namespace": "user/repo"
+            },
+            "repository": {
+                "url": "git@example.com/user/repo.git"
+            }
+        }"#;
+        let push = serde_json::from_str::<Push>(input).unwrap();
+        let event = push.event();
+
+        assert_eq!(push.action(), "created".to_string());
+        assert_eq!(event.kind, "tag_push".to_string());
+        assert_eq!(event.branch, "".to_string());
+        assert_eq!(event.tag, "v1.0.0".to_string());
+        assert_eq!(push.git_source().reference, Ref::Tag("v1.0.0".to_string()));
+    }
+
+    #[test]
+    fn it_deserializes_pipeline() {
+        let input = r#"{
+            "object_kind": "pipeline",
+            "object_attributes": {
+                "id": 31,
+                "ref": "main",
+                "tag": false,
+                "sha": "bcbb5ec396a2c0f828686f14fac9b80b780504f2",
+                "source": "push",
+                "status": "success"
+            },
+            "project": {
+                "path_with_namespace": "user/repo",
+                "git_http_url": "https://example.com/user/repo.git"
+            }
+        }"#;
+        let pipeline = serde_json::from_str::<Pipeline>(input).unwrap();
+        let event = pipeline.event();
+
+        assert_eq!(pipeline.id(), 31);
+        assert_eq!(
+            pipeline.sha(),
+            "bcbb5ec396a2c0f828686f14fac9b80b780504f2".to_string()
+        );
+        assert_eq!(event.kind, "pipeline".to_string());
+        assert_eq!(event.branch, "main".to_string());
+        assert_eq!(event.action, "push".to_string());
+        assert_eq!(event.state, "success".to_string());
+
+        let source = pipeline.git_source();
+        assert_eq!(source.url, "https://example.com/user/repo.git".to_string());
+        assert_eq!(source.path, "user/repo".to_string());
+        assert_eq!(source.reference, Ref::Branch("main".to_string()));
+        assert_eq!(
+            source.sha,
+            Some("bcbb5ec396a2c0f828686f14fac9b80b780504f2".to_string())
+        );
+    }
+
+    #[test]
+    fn it_builds_manual_push() {
+        let push = Push::manual("https://gitlab.com/group/repo.git", Some("staging"), None);
+
+        assert_eq!(push.branch(), "staging".to_string());
+        assert_eq!(push.checkout_sha(), "undefined".to_string());
+        assert_eq!(
+            push.repository_url(),
+            "https://gitlab.com/group/repo.git".to_string()
+        );
+        assert_eq!(push.event().kind, "push".to_string());
+        assert_eq!(push.event().branch, "staging".to_string());
+        assert_eq!(push.git_source().path, "group/repo".to_string());
+    }
+
+    #[test]
+    fn it_parses_events_by_name() {
+        let pipeline = r#"{
+            "object_attributes": {
+                "ref": "v1.0.0",
+                "tag": true,
+                "status": "failed"
+            },
+            "project": {}
+        }"#;
+
+        match Event::parse("Pipeline Hook", pipeline.as_bytes()).unwrap() {
+            Some(Event::Pipeline(pipeline)) => {
+                assert_eq!(pipeline.event().tag, "v1.0.0".to_string());
+                assert_eq!(pipeline.event().state, "failed".to_string());
+            }
+            _ => panic!("expected pipeline event"),
+        }
+        assert!(Event::parse("Note Hook", b"{}").unwrap().is_none());
+        assert!(Event::parse("Merge Request Hook", b"{}").is_err());
     }
 }